@@ -4,7 +4,7 @@ use quote::quote;
 use syn::{
     parse::{Parse, ParseStream, Result},
     punctuated::Punctuated,
-    Attribute, Error, Ident, LitStr, Token,
+    Attribute, Error, Ident, LitBool, LitStr, Path, Token, Type,
 };
 
 /// Struct that represents the Sexpy attribute syntax.
@@ -53,8 +53,7 @@ pub trait SexpyAttr<EnumT: Parse + std::fmt::Debug> {
                 Ok(a_enum) => a_enum,
                 Err(e) => abort!(e.span(), "{}", e),
             })
-            .map(|attr_syn| attr_syn.attrs)
-            .flatten();
+            .flat_map(|attr_syn| attr_syn.attrs);
         let mut res = Self::default();
         for e in attr_enums {
             res.add_enum(&e);
@@ -63,18 +62,85 @@ pub trait SexpyAttr<EnumT: Parse + std::fmt::Debug> {
     }
 }
 
+/// Generates a `head` parser for `head`, accepting `aliases` as well if
+/// there are any
+pub fn head_syn(
+    head: &str,
+    aliases: &[String],
+    deprecated: bool,
+    inner: TokenStream,
+) -> TokenStream {
+    if aliases.is_empty() {
+        quote! { ::sexpy::parsers::head(#head, #inner) }
+    } else {
+        quote! {
+            ::sexpy::parsers::head_or_alias(
+                #head, &[#(#aliases),*], #deprecated, #inner)
+        }
+    }
+}
+
+/// Parses what follows a flag attribute: either nothing, meaning `true`,
+/// or `= <bool>`
+fn parse_flag(input: ParseStream) -> Result<bool> {
+    if input.peek(Token![=]) {
+        let _ = input.parse::<Token![=]>()?;
+        let lit: LitBool = input.parse()?;
+        Ok(lit.value)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Parses what follows a string attribute: `= "<string>"`
+fn parse_str(input: ParseStream) -> Result<LitStr> {
+    let _ = input.parse::<Token![=]>()?;
+    input.parse()
+}
+
+/// The error for an attribute that is not one of `known`
+fn unknown_key(field: &Ident, known: &[&str]) -> Error {
+    let msg = format!(
+        "`{}` is not a known attribute, expected one of: {}",
+        field,
+        known.join(", ")
+    );
+    Error::new(field.span(), msg)
+}
+
 // =============== Type Level Attributes ================ //
 pub struct TyAttrs {
     pub nohead: bool,
     pub head: Option<String>,
     pub surround: bool,
+    pub expect: Option<String>,
+    pub validate: Option<Path>,
+    pub transparent: bool,
+    pub keyword: bool,
+    pub rename_all: Option<RenameRule>,
+    pub choice: Option<Choice>,
+    pub aliases: Vec<String>,
+    pub deprecated: bool,
+    pub case_insensitive: bool,
+    pub deny_unreachable: bool,
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum TyAttrEnum {
     NoHead(bool, Span),
     Head(String, Span),
     Surround(bool, Span),
+    Expect(String, Span),
+    Validate(Path, Span),
+    Transparent(bool, Span),
+    Keyword(bool, Span),
+    RenameAll(RenameRule, Span),
+    Choice(Choice, Span),
+    Alias(String, Span),
+    Deprecated(bool, Span),
+    CaseInsensitive(bool, Span),
+    DenyUnreachable(bool, Span),
 }
 
 impl SexpyAttr<TyAttrEnum> for TyAttrs {
@@ -83,6 +149,16 @@ impl SexpyAttr<TyAttrEnum> for TyAttrs {
             nohead: false,
             head: None,
             surround: true,
+            expect: None,
+            validate: None,
+            transparent: false,
+            keyword: false,
+            rename_all: None,
+            choice: None,
+            aliases: vec![],
+            deprecated: false,
+            case_insensitive: false,
+            deny_unreachable: false,
         }
     }
 
@@ -91,7 +167,8 @@ impl SexpyAttr<TyAttrEnum> for TyAttrs {
 
         if !self.nohead {
             if let Some(head) = &self.head {
-                res = quote! { (::sexpy::parsers::head(#head, #res)) }
+                let head = head_syn(head, &self.aliases, self.deprecated, res);
+                res = quote! { (#head) }
             }
         }
 
@@ -99,6 +176,10 @@ impl SexpyAttr<TyAttrEnum> for TyAttrs {
             res = quote! { (|i: &'a str| ::sexpy::parsers::surround(#res, i)) }
         }
 
+        if let Some(msg) = &self.expect {
+            res = quote! { ::sexpy::error::expect(#msg, #res) }
+        }
+
         res
     }
 
@@ -108,97 +189,423 @@ impl SexpyAttr<TyAttrEnum> for TyAttrs {
             NoHead(b, _) => self.nohead = *b,
             Head(s, _) => self.head = Some(s.to_string()),
             Surround(b, _) => self.surround = *b,
+            Expect(s, _) => self.expect = Some(s.to_string()),
+            Validate(p, _) => self.validate = Some(p.clone()),
+            Transparent(b, _) => self.transparent = *b,
+            Keyword(b, _) => self.keyword = *b,
+            RenameAll(r, _) => self.rename_all = Some(*r),
+            Choice(c, _) => self.choice = Some(*c),
+            Alias(a, _) => self.aliases.push(a.to_string()),
+            Deprecated(b, _) => self.deprecated = *b,
+            CaseInsensitive(b, _) => self.case_insensitive = *b,
+            DenyUnreachable(b, _) => self.deny_unreachable = *b,
         }
     }
 }
 
+impl TyAttrEnum {
+    const KEYS: &'static [&'static str] = &[
+        "head",
+        "nohead",
+        "surround",
+        "nosurround",
+        "expect",
+        "validate",
+        "transparent",
+        "keyword",
+        "alias",
+        "deprecated",
+        "rename_all",
+        "choice",
+        "case_insensitive",
+        "deny_unreachable",
+    ];
+}
+
 impl Parse for TyAttrEnum {
     fn parse(input: ParseStream) -> Result<Self> {
         use TyAttrEnum::*;
 
         let field: Ident = input.parse()?;
+        let span = field.span();
 
         match field.to_string().as_ref() {
             "head" => {
-                let _ = input.parse::<Token![=]>()?;
-                let lit: LitStr = input.parse()?;
-                let lit_val = lit.value();
-                Ok(Head(lit_val, lit.span()))
+                let lit = parse_str(input)?;
+                Ok(Head(lit.value(), lit.span()))
+            }
+            "nohead" => Ok(NoHead(parse_flag(input)?, span)),
+            "surround" => Ok(Surround(parse_flag(input)?, span)),
+            "nosurround" => Ok(Surround(!parse_flag(input)?, span)),
+            "expect" => {
+                let lit = parse_str(input)?;
+                Ok(Expect(lit.value(), lit.span()))
+            }
+            "validate" => {
+                let lit = parse_str(input)?;
+                Ok(Validate(lit.parse()?, lit.span()))
+            }
+            "transparent" => Ok(Transparent(parse_flag(input)?, span)),
+            "keyword" => Ok(Keyword(parse_flag(input)?, span)),
+            "alias" => {
+                let lit = parse_str(input)?;
+                Ok(Alias(lit.value(), lit.span()))
+            }
+            "deprecated" => Ok(Deprecated(parse_flag(input)?, span)),
+            "case_insensitive" => Ok(CaseInsensitive(parse_flag(input)?, span)),
+            "deny_unreachable" => Ok(DenyUnreachable(parse_flag(input)?, span)),
+            "rename_all" => {
+                let lit = parse_str(input)?;
+                match RenameRule::from_str(&lit.value()) {
+                    Some(rule) => Ok(RenameAll(rule, lit.span())),
+                    None => Err(Error::new(
+                        lit.span(),
+                        format!(
+                            "unknown casing `{}`, expected one of: {}",
+                            lit.value(),
+                            RenameRule::NAMES.join(", ")
+                        ),
+                    )),
+                }
+            }
+            "choice" => {
+                let lit = parse_str(input)?;
+                match self::Choice::from_str(&lit.value()) {
+                    Some(c) => Ok(Choice(c, lit.span())),
+                    None => Err(Error::new(
+                        lit.span(),
+                        format!(
+                            "unknown choice `{}`, expected one of: {}",
+                            lit.value(),
+                            self::Choice::NAMES.join(", ")
+                        ),
+                    )),
+                }
+            }
+            _ => Err(unknown_key(&field, Self::KEYS)),
+        }
+    }
+}
+
+/// How the variants of an enum are chosen between, see
+/// `sexpy::parsers::Choice`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Choice {
+    Ordered,
+    Longest,
+    Unique,
+}
+
+impl Choice {
+    pub const NAMES: &'static [&'static str] =
+        &["ordered", "longest", "unique"];
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "ordered" => Some(Choice::Ordered),
+            "longest" => Some(Choice::Longest),
+            "unique" => Some(Choice::Unique),
+            _ => None,
+        }
+    }
+
+    /// The matching `sexpy::parsers::Choice`
+    pub fn to_tokens(self) -> TokenStream {
+        match self {
+            Choice::Ordered => quote! { ::sexpy::parsers::Choice::Ordered },
+            Choice::Longest => quote! { ::sexpy::parsers::Choice::Longest },
+            Choice::Unique => quote! { ::sexpy::parsers::Choice::Unique },
+        }
+    }
+}
+
+/// Casing rules for `rename_all`
+#[derive(Debug, Clone, Copy)]
+pub enum RenameRule {
+    Lower,
+    Upper,
+    Kebab,
+    Snake,
+    Camel,
+    Pascal,
+    ScreamingSnake,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    pub const NAMES: &'static [&'static str] = &[
+        "lowercase",
+        "UPPERCASE",
+        "kebab-case",
+        "snake_case",
+        "camelCase",
+        "PascalCase",
+        "SCREAMING_SNAKE_CASE",
+        "SCREAMING-KEBAB-CASE",
+    ];
+
+    fn from_str(s: &str) -> Option<Self> {
+        use RenameRule::*;
+        match s {
+            "lowercase" => Some(Lower),
+            "UPPERCASE" => Some(Upper),
+            "kebab-case" => Some(Kebab),
+            "snake_case" => Some(Snake),
+            "camelCase" => Some(Camel),
+            "PascalCase" => Some(Pascal),
+            "SCREAMING_SNAKE_CASE" | "SCREAMING" => Some(ScreamingSnake),
+            "SCREAMING-KEBAB-CASE" => Some(ScreamingKebab),
+            _ => None,
+        }
+    }
+
+    /// Apply the casing rule to a Rust identifier such as `PalmTree`
+    pub fn apply(self, name: &str) -> String {
+        use RenameRule::*;
+        let words = split_words(name);
+        let capitalize = |w: &String| {
+            let mut cs = w.chars();
+            match cs.next() {
+                Some(c) => c.to_uppercase().chain(cs).collect(),
+                None => String::new(),
+            }
+        };
+        match self {
+            Lower => words.concat(),
+            Upper => words.concat().to_uppercase(),
+            Kebab => words.join("-"),
+            Snake => words.join("_"),
+            Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| if i == 0 { w.clone() } else { capitalize(w) })
+                .collect(),
+            Pascal => words.iter().map(capitalize).collect(),
+            ScreamingSnake => words.join("_").to_uppercase(),
+            ScreamingKebab => words.join("-").to_uppercase(),
+        }
+    }
+}
+
+/// Splits an identifier into lowercase words at underscores and case
+/// changes, keeping acronyms together: `HTTPServer` is `http`, `server`.
+fn split_words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = vec![];
+    let mut cur = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' {
+            if !cur.is_empty() {
+                words.push(std::mem::take(&mut cur));
+            }
+            continue;
+        }
+        if c.is_uppercase() && !cur.is_empty() {
+            let prev = chars[i - 1];
+            let next_lower =
+                matches!(chars.get(i + 1), Some(n) if n.is_lowercase());
+            if !prev.is_uppercase() || next_lower {
+                words.push(std::mem::take(&mut cur));
             }
-            "nohead" => Ok(NoHead(true, field.span())),
-            "nosurround" => Ok(Surround(false, field.span())),
-            _ => Err(Error::new(
-                field.span(),
-                format!("`{}` is not a known field", field),
-            )),
         }
+        cur.extend(c.to_lowercase());
     }
+    if !cur.is_empty() {
+        words.push(cur);
+    }
+    words
 }
 
 // ============ field level attributes ================ //
 pub struct FieldAttrs {
+    pub nohead: bool,
     pub head: Option<String>,
     pub surround: bool,
+    pub expect: Option<String>,
+    pub validate: Option<Path>,
+    pub convert: Option<Conversion>,
+    pub aliases: Vec<String>,
+    pub deprecated: bool,
+    pub comments: bool,
+}
+
+/// How a field is parsed when it is not parsed with its own `Sexpy` impl
+#[derive(Debug, Clone)]
+pub enum Conversion {
+    /// parse with `<module>::sexp_parse`
+    With(Path),
+    /// parse the given type and convert it with `From`
+    From(Type),
+    /// parse the given type and convert it with `TryFrom`
+    TryFrom(Type),
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum FieldAttrEnum {
+    NoHead(bool, Span),
     Head(String, Span),
     Surround(bool, Span),
+    Expect(String, Span),
+    Validate(Path, Span),
+    Convert(Conversion, Span),
+    Alias(String, Span),
+    Deprecated(bool, Span),
+    Comments(bool, Span),
+}
+
+impl FieldAttrs {
+    /// The parser for a field of type `ty`, before any of the
+    /// syntax changes made by `apply`
+    pub fn parser(&self, ty: &Type) -> TokenStream {
+        // comments are not parsed, they are the ones skipped before
+        // the whole form
+        if self.comments {
+            return quote! {
+                (|i: &'a str| ::sexpy::nom::IResult::Ok(
+                    (i, ::sexpy::parsers::leading_comments(input))))
+            };
+        }
+        match &self.convert {
+            None => quote! { <#ty>::sexp_parse },
+            Some(Conversion::With(module)) => quote! { #module::sexp_parse },
+            Some(Conversion::From(from)) => quote! {
+                ::sexpy::nom::combinator::map(
+                    <#from>::sexp_parse,
+                    <#ty as ::std::convert::From<#from>>::from)
+            },
+            Some(Conversion::TryFrom(from)) => quote! {
+                ::sexpy::parsers::convert(
+                    <#from>::sexp_parse,
+                    <#ty as ::std::convert::TryFrom<#from>>::try_from)
+            },
+        }
+    }
 }
 
 impl SexpyAttr<FieldAttrEnum> for FieldAttrs {
     fn default() -> Self {
         FieldAttrs {
+            nohead: false,
             head: None,
             surround: false,
+            expect: None,
+            validate: None,
+            convert: None,
+            aliases: vec![],
+            deprecated: false,
+            comments: false,
         }
     }
 
     fn apply(&self, ts: TokenStream) -> TokenStream {
         let mut res = ts;
-        if let Some(head) = &self.head {
-            res = quote! {
-                ::sexpy::nom::sequence::preceded(
-                    ::sexpy::parsers::wordbreak0,
-                    ::sexpy::parsers::head(#head, #res))
-            }
+        let head = self.head.as_ref().filter(|_| !self.nohead);
+        if let Some(head) = head {
+            res = head_syn(head, &self.aliases, self.deprecated, res)
         };
 
         if self.surround {
             res = quote! { (|i: &'a str| ::sexpy::parsers::surround(#res, i)) }
         };
 
+        if let Some(check) = &self.validate {
+            res = quote! { ::sexpy::parsers::validate(#res, #check) }
+        }
+
+        if let Some(msg) = &self.expect {
+            res = quote! { ::sexpy::error::expect(#msg, #res) }
+        }
+
+        if head.is_some() || self.surround {
+            res = quote! {
+                ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #res)
+            }
+        }
+
         res
     }
 
     fn add_enum(&mut self, e: &FieldAttrEnum) {
         use FieldAttrEnum::*;
         match e {
+            NoHead(b, _) => self.nohead = *b,
             Head(s, _) => self.head = Some(s.to_string()),
             Surround(b, _) => self.surround = *b,
+            Expect(s, _) => self.expect = Some(s.to_string()),
+            Validate(p, _) => self.validate = Some(p.clone()),
+            Convert(c, span) => {
+                if self.convert.is_some() {
+                    abort!(
+                        *span,
+                        "only one of `with`, `from` and `try_from` can be used"
+                    )
+                }
+                self.convert = Some(c.clone())
+            }
+            Alias(a, _) => self.aliases.push(a.to_string()),
+            Deprecated(b, _) => self.deprecated = *b,
+            Comments(b, _) => self.comments = *b,
         }
     }
 }
 
+impl FieldAttrEnum {
+    const KEYS: &'static [&'static str] = &[
+        "head",
+        "nohead",
+        "surround",
+        "nosurround",
+        "expect",
+        "validate",
+        "with",
+        "from",
+        "try_from",
+        "alias",
+        "deprecated",
+        "comments",
+    ];
+}
+
 impl Parse for FieldAttrEnum {
     fn parse(input: ParseStream) -> Result<Self> {
         use FieldAttrEnum::*;
         let field: Ident = input.parse()?;
+        let span = field.span();
 
         match field.to_string().as_ref() {
             "head" => {
-                let _ = input.parse::<Token![=]>()?;
-                let lit: LitStr = input.parse()?;
-                let lit_val = lit.value();
-                Ok(Head(lit_val, lit.span()))
-            }
-            "surround" => Ok(Surround(true, field.span())),
-            // "nosurround" => Ok(Surround(false, field.span())),
-            _ => Err(Error::new(
-                field.span(),
-                format!("`{}` is not a known field", field),
-            )),
+                let lit = parse_str(input)?;
+                Ok(Head(lit.value(), lit.span()))
+            }
+            "nohead" => Ok(NoHead(parse_flag(input)?, span)),
+            "surround" => Ok(Surround(parse_flag(input)?, span)),
+            "nosurround" => Ok(Surround(!parse_flag(input)?, span)),
+            "expect" => {
+                let lit = parse_str(input)?;
+                Ok(Expect(lit.value(), lit.span()))
+            }
+            "validate" => {
+                let lit = parse_str(input)?;
+                Ok(Validate(lit.parse()?, lit.span()))
+            }
+            "with" | "from" | "try_from" => {
+                let lit = parse_str(input)?;
+                let conv = match field.to_string().as_ref() {
+                    "with" => Conversion::With(lit.parse()?),
+                    "from" => Conversion::From(lit.parse()?),
+                    _ => Conversion::TryFrom(lit.parse()?),
+                };
+                Ok(Convert(conv, lit.span()))
+            }
+            "alias" => {
+                let lit = parse_str(input)?;
+                Ok(Alias(lit.value(), lit.span()))
+            }
+            "deprecated" => Ok(Deprecated(parse_flag(input)?, span)),
+            "comments" => Ok(Comments(parse_flag(input)?, span)),
+            _ => Err(unknown_key(&field, Self::KEYS)),
         }
     }
 }
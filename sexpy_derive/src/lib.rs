@@ -1,11 +1,14 @@
 mod attrs;
+mod shadow;
 
 extern crate proc_macro;
 
-use attrs::{FieldAttrs, SexpyAttr, TyAttrs};
+use attrs::{Choice, FieldAttrs, SexpyAttr, TyAttrs};
 use proc_macro2::{Span, TokenStream};
-use proc_macro_error::{abort_call_site, proc_macro_error};
+use proc_macro_error::{abort, abort_call_site, proc_macro_error};
 use quote::quote;
+use shadow::Pattern;
+use syn::spanned::Spanned;
 use syn::{
     parse_macro_input, Data, DataEnum, DataStruct, DeriveInput, Fields, Ident,
     Variant,
@@ -32,19 +35,53 @@ fn impl_sexpy(ast: &DeriveInput) -> TokenStream {
     // parse type level attributes
     let mut attrs = TyAttrs::from_attributes(&ast.attrs);
 
-    // default head is `name`
+    // default head is `name`, cased by `rename_all` if it is given
     if attrs.head.is_none() {
-        attrs.head = Some(name.to_string().to_lowercase())
+        attrs.head = Some(default_head(name, &attrs))
     };
 
     // check what type of thing we have and call the corresponding
     // parser
     let parser: TokenStream = match &ast.data {
-        Data::Enum(data) => enum_parser(&name, data, &attrs),
-        Data::Struct(data) => struct_parser(&name, data, &mut attrs),
+        Data::Struct(data) if attrs.transparent => {
+            transparent_parser(name, data)
+        }
+        Data::Enum(_) if attrs.transparent => {
+            abort_call_site!("`transparent` only works on structs")
+        }
+        Data::Enum(data) if attrs.keyword => keyword_parser(name, data, &attrs),
+        Data::Struct(_) if attrs.keyword => {
+            abort_call_site!("`keyword` only works on enums")
+        }
+        Data::Struct(_) if attrs.choice.is_some() => {
+            abort_call_site!("`choice` only works on enums")
+        }
+        Data::Enum(data) => enum_parser(name, data, &attrs),
+        Data::Struct(data) => struct_parser(name, data, &mut attrs),
         _ => abort_call_site!("Only works on structs or enums"),
     };
 
+    // check the whole value if the user asked for it
+    let parser = match &attrs.validate {
+        Some(check) => quote! {
+            ::sexpy::parsers::validate(|input: &'a str| { #parser }, #check)(input)
+        },
+        None => parser,
+    };
+
+    // the comments before the value are attached to it once it parses
+    let parser = if has_comments(&ast.data) {
+        quote! { ::sexpy::parsers::attach_comments(input, |input: &'a str| { #parser }) }
+    } else {
+        parser
+    };
+
+    let parser = if attrs.case_insensitive {
+        quote! { ::sexpy::parsers::ignore_case(input, |input: &'a str| { #parser }) }
+    } else {
+        parser
+    };
+
     // construct Sexpy impl
     quote! {
         impl Sexpy for #name {
@@ -52,12 +89,21 @@ fn impl_sexpy(ast: &DeriveInput) -> TokenStream {
                 ::sexpy::nom::IResult<&'a str, Self, ::sexpy::error::SexpyError<&'a str>>
             where
                 Self: Sized {
-                #parser
+                ::sexpy::parsers::nested(input, |input: &'a str| { #parser })
             }
         }
     }
 }
 
+/// The head used for `name` when none is given: the lowercased name,
+/// or the name cased by `rename_all`
+fn default_head(name: &Ident, attrs: &TyAttrs) -> String {
+    match attrs.rename_all {
+        Some(rule) => rule.apply(&name.to_string()),
+        None => name.to_string().to_lowercase(),
+    }
+}
+
 /// Generates the parser for `enum` types
 fn enum_parser(
     parse_name: &Ident,
@@ -69,24 +115,63 @@ fn enum_parser(
         abort_call_site!("Can not construct enum with no cases.")
     }
 
-    // generate a parser for each variant
+    // generate a parser for each variant, remembering the pattern it
+    // matches to check that it can be reached
+    let mut patterns = vec![];
+    let mut heads = vec![];
     let parsers: Vec<TokenStream> = data
         .variants
         .iter()
         .map(|var| {
-            let mut attrs = FieldAttrs::from_attributes(&var.attrs);
-            variant_parser(parse_name, var, &mut attrs)
+            let mut var_attrs = FieldAttrs::from_attributes(&var.attrs);
+            // with `rename_all`, variants are headed by their own name
+            if var_attrs.head.is_none()
+                && !var_attrs.nohead
+                && attrs.rename_all.is_some()
+            {
+                var_attrs.head = Some(default_head(&var.ident, attrs))
+            }
+            if var_attrs.head.is_none() && !var_attrs.aliases.is_empty() {
+                abort!(var.ident.span(), "`alias` needs a `head` to alias")
+            }
+            // case insensitive heads are dispatched on in lowercase
+            let mut pattern = Pattern::new(var, &var_attrs);
+            let mut dispatch = dispatch_heads(&var_attrs);
+            if attrs.case_insensitive {
+                pattern = pattern.lowercase();
+                dispatch.iter_mut().flatten().for_each(|h| {
+                    h.make_ascii_lowercase();
+                });
+            }
+            patterns.push(pattern);
+            heads.push(dispatch);
+            variant_parser(parse_name, var, &mut var_attrs)
         })
         .collect();
+    let choice = attrs.choice.unwrap_or(Choice::Ordered);
+    if attrs.deny_unreachable {
+        shadow::check(&patterns, choice);
+    }
 
-    // we can't use `alt` if there is only one parser
-    let parser = if parsers.len() == 1 {
+    let parser = if choice == Choice::Ordered
+        && parsers.len() > 1
+        && heads.iter().any(Option::is_some)
+    {
+        dispatch_parser(data, &parsers, &heads)
+    } else if parsers.len() == 1 {
+        // there is nothing to choose between
         quote! {
             #( #parsers )*
         }
     } else {
+        // `choice` takes a slice, so there is no limit on the number of
+        // variants, and discards the warnings of the ones it does not pick
+        let mode = choice.to_tokens();
+        let names = data.variants.iter().map(|var| var.ident.to_string());
         quote! {
-            ::sexpy::nom::branch::alt((#( #parsers ),*))
+            ::sexpy::parsers::choice(#mode, &[#(
+                (#names, &(#parsers) as ::sexpy::parsers::Alternative<'_, 'a, Self>)
+            ),*])
         }
     };
 
@@ -99,24 +184,197 @@ fn enum_parser(
     }
 }
 
+/// The spellings of the head a variant parsed with `attrs` can be
+/// dispatched on, if it starts with one
+fn dispatch_heads(attrs: &FieldAttrs) -> Option<Vec<String>> {
+    match &attrs.head {
+        // a surrounded variant starts with a delimiter, not its head
+        Some(head) if !attrs.nohead && !attrs.surround => Some(
+            std::iter::once(head)
+                .chain(&attrs.aliases)
+                .cloned()
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Generates an ordered choice between `parsers` that reads the next word
+/// once and only tries the variants whose head is that word, along with the
+/// variants that can't be dispatched on. Variants are still tried in
+/// declaration order, so this behaves like trying every variant in turn.
+fn dispatch_parser(
+    data: &DataEnum,
+    parsers: &[TokenStream],
+    heads: &[Option<Vec<String>>],
+) -> TokenStream {
+    let names: Vec<String> =
+        data.variants.iter().map(|v| v.ident.to_string()).collect();
+    let vars: Vec<Ident> = (0..parsers.len())
+        .map(|idx| Ident::new(&format!("v_{}", idx), Span::call_site()))
+        .collect();
+
+    // an ordered choice between the variants at `idxs`
+    let choice = |idxs: &[usize]| {
+        if idxs.len() == 1 {
+            let v = &vars[idxs[0]];
+            return quote! { #v(i) };
+        }
+        let alts = idxs.iter().map(|&idx| {
+            let (name, v) = (&names[idx], &vars[idx]);
+            quote! { (#name, &#v as ::sexpy::parsers::Alternative<'_, 'a, Self>) }
+        });
+        quote! {
+            ::sexpy::parsers::choice(
+                ::sexpy::parsers::Choice::Ordered, &[#(#alts),*])(i)
+        }
+    };
+
+    let mut words: Vec<&String> = vec![];
+    for word in heads.iter().flatten().flatten() {
+        if !words.contains(&word) {
+            words.push(word)
+        }
+    }
+    let arms = words.iter().map(|word| {
+        let idxs: Vec<usize> = heads
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| match h {
+                Some(h) => h.contains(word),
+                None => true,
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        let body = choice(&idxs);
+        quote! { #word => #body, }
+    });
+    // without a known head, every variant is tried so that the error
+    // lists all of the heads
+    let all: Vec<usize> = (0..parsers.len()).collect();
+    let fallback = choice(&all);
+
+    quote! {
+        (|i: &'a str| {
+            #( let #vars = #parsers; )*
+            // the variant heads come after any space following the type head
+            let head = ::sexpy::parsers::wordbreak0(i).map_or(i, |(rest, _)| rest);
+            match &*::sexpy::parsers::dispatch_word(head) {
+                #( #arms )*
+                _ => #fallback,
+            }
+        })
+    }
+}
+
+/// Generates the parser for `keyword` enums, where every variant is
+/// parsed from a single bare word
+fn keyword_parser(
+    parse_name: &Ident,
+    data: &DataEnum,
+    attrs: &TyAttrs,
+) -> TokenStream {
+    if data.variants.is_empty() {
+        abort_call_site!("Can not construct enum with no cases.")
+    }
+
+    let mut patterns = vec![];
+    let parsers: Vec<TokenStream> = data
+        .variants
+        .iter()
+        .map(|var| {
+            let name = &var.ident;
+            if !var.fields.is_empty() {
+                abort!(
+                    name.span(),
+                    "`keyword` enum variants can not have fields"
+                )
+            }
+            let var_attrs = FieldAttrs::from_attributes(&var.attrs);
+            let kw = var_attrs
+                .head
+                .clone()
+                .unwrap_or_else(|| default_head(name, attrs));
+            let words = std::iter::once(&kw).chain(&var_attrs.aliases);
+            let pattern = Pattern::keyword(var, words.cloned().collect());
+            patterns.push(if attrs.case_insensitive {
+                pattern.lowercase()
+            } else {
+                pattern
+            });
+            // keywords are spelled like heads, aliases included
+            let word = if var_attrs.aliases.is_empty() {
+                quote! { ::sexpy::parsers::word(#kw) }
+            } else {
+                let aliases = &var_attrs.aliases;
+                let deprecated = var_attrs.deprecated;
+                quote! {
+                    ::sexpy::parsers::word_or_alias(
+                        #kw, &[#(#aliases),*], #deprecated)
+                }
+            };
+            quote! {
+                ::sexpy::nom::combinator::map(#word, |_| #parse_name::#name)
+            }
+        })
+        .collect();
+    if attrs.deny_unreachable {
+        shadow::check(&patterns, Choice::Ordered);
+    }
+
+    let parser = if parsers.len() == 1 {
+        quote! { #( #parsers )* }
+    } else {
+        let names = data.variants.iter().map(|var| var.ident.to_string());
+        quote! {
+            ::sexpy::parsers::choice(::sexpy::parsers::Choice::Ordered, &[#(
+                (#names, &#parsers as ::sexpy::parsers::Alternative<'_, 'a, Self>)
+            ),*])
+        }
+    };
+
+    let parser = match &attrs.expect {
+        Some(msg) => quote! { ::sexpy::error::expect(#msg, #parser) },
+        None => parser,
+    };
+
+    quote! {
+        #parser(input)
+    }
+}
+
 /// Generates the parser for `struct` types
 fn struct_parser(
     struct_name: &Ident,
     data: &DataStruct,
     attrs: &mut TyAttrs,
 ) -> TokenStream {
-    // generate a parser for each field
-    let fields = field_parser(&data.fields);
-
-    // let mut attrs = FieldAttrs::from_attributes(&var.attrs);
-    // variant_parser(parse_name, var, &mut attrs)
-
     // get the identifiers from the fields
     let idents = field_idents(&data.fields);
-    let idents_str: Vec<String> =
-        idents.iter().map(|x| x.to_string()).collect();
     let bindings = field_binder_syn(&idents);
 
+    // generate a parser for each field. fields are labeled with their name,
+    // or position in tuple structs, unless the user gave an expectation
+    let fields: Vec<TokenStream> = field_parser(&data.fields)
+        .into_iter()
+        .zip(&idents)
+        .enumerate()
+        .map(|(idx, ((fld, attrs), id))| {
+            let fld = quote! {
+                ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #fld)
+            };
+            if attrs.expect.is_some() {
+                fld
+            } else {
+                let label = match &data.fields {
+                    Fields::Named(_) => id.to_string(),
+                    _ => format!("field {}", idx),
+                };
+                quote! { ::sexpy::error::context(#label, #fld) }
+            }
+        })
+        .collect();
+
     // turn the field parsers into a single tokenstream
     let parser = if data.fields.is_empty() {
         quote! {
@@ -124,33 +382,61 @@ fn struct_parser(
         }
     } else if data.fields.len() <= 1 {
         quote! {
-            #(::sexpy::error::context(#idents_str, ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #fields)))*
+            #(#fields)*
         }
     } else {
-        let fst_fld = &fields[0];
-        let fst_id = &idents_str[0];
-        let rest_fld = &fields[1..];
-        let rest_id = &idents_str[1..];
-        quote! {
-            ::sexpy::nom::sequence::tuple((
-                ::sexpy::error::context(#fst_id, ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #fst_fld)),
-                #(::sexpy::error::context(#rest_id, ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #rest_fld))),*
-            ))
-        }
+        sequence_syn(&fields, &idents)
     };
 
     // apply the syntax changes from the attributes and construct
     // final syntax
     let ts = attrs.apply(parser);
+    let constr =
+        constructor_syn(quote! { #struct_name }, &data.fields, &idents);
     quote! {
         let (next, #bindings) = #ts(input)?;
-        Ok((next, #struct_name { #(#idents),* }))
+        Ok((next, #constr))
     }
 }
 
+/// Generates the parser for `transparent` structs, which is just the
+/// parser of their only field
+fn transparent_parser(struct_name: &Ident, data: &DataStruct) -> TokenStream {
+    if data.fields.len() != 1 {
+        abort_call_site!("`transparent` structs must have exactly one field")
+    }
+
+    let (fld, _) = field_parser(&data.fields).remove(0);
+    let constr = match &data.fields {
+        Fields::Named(fields) => {
+            let id = &fields.named[0].ident;
+            quote! { #struct_name { #id: res } }
+        }
+        _ => quote! { #struct_name(res) },
+    };
+
+    quote! {
+        let (next, res) = #fld(input)?;
+        Ok((next, #constr))
+    }
+}
+
+/// Whether any field of `data` is marked `#[sexpy(comments)]`
+fn has_comments(data: &Data) -> bool {
+    let fields: Vec<&Fields> = match data {
+        Data::Struct(data) => vec![&data.fields],
+        Data::Enum(data) => data.variants.iter().map(|v| &v.fields).collect(),
+        Data::Union(_) => vec![],
+    };
+    fields
+        .into_iter()
+        .flatten()
+        .any(|f| FieldAttrs::from_attributes(&f.attrs).comments)
+}
+
 /// Generates a vec of parsers that parse each field
-/// in an enum or struct.
-fn field_parser(fields: &Fields) -> Vec<TokenStream> {
+/// in an enum or struct, along with the attributes of each field.
+fn field_parser(fields: &Fields) -> Vec<(TokenStream, FieldAttrs)> {
     let field_iter = match fields {
         Fields::Unnamed(fields) => fields.unnamed.iter(),
         Fields::Named(fields) => fields.named.iter(),
@@ -158,12 +444,21 @@ fn field_parser(fields: &Fields) -> Vec<TokenStream> {
     };
     field_iter
         .map(|f| {
-            let ty = &f.ty;
-            let syn = quote! {
-                <#ty>::sexp_parse
-            };
             let attrs = FieldAttrs::from_attributes(&f.attrs);
-            attrs.apply(syn)
+            if attrs.head.is_none() && !attrs.aliases.is_empty() {
+                abort!(f.ty.span(), "`alias` needs a `head` to alias")
+            }
+            if attrs.comments
+                && (attrs.head.is_some() || attrs.convert.is_some())
+            {
+                abort!(
+                    f.ty.span(),
+                    "`comments` fields are not parsed, so they can't have a \
+                     `head`, `with`, `from` or `try_from`"
+                )
+            }
+            let syn = attrs.parser(&f.ty);
+            (attrs.apply(syn), attrs)
         })
         .collect()
 }
@@ -198,39 +493,40 @@ fn variant_parser(
     attrs: &mut FieldAttrs,
 ) -> TokenStream {
     let name = &var.ident;
-    let fld_par = field_parser(&var.fields);
+    let fld_par: Vec<TokenStream> = field_parser(&var.fields)
+        .into_iter()
+        .map(|(fld, _)| fld)
+        .collect();
     let idents = field_idents(&var.fields);
     let binders = field_binder_syn(&idents);
 
-    let context = format!("Parsing {}", name.to_string());
-
+    let fld_par: Vec<TokenStream> = fld_par
+        .into_iter()
+        .map(|fld| {
+            quote! {
+                ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #fld)
+            }
+        })
+        .collect();
     let field_syn = if var.fields.is_empty() {
         quote! { ::sexpy::parsers::wordbreak0 }
     } else if var.fields.len() == 1 {
-        quote! {
-            #( ::sexpy::error::context(#context, ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #fld_par)) )*
-        }
+        quote! { #( #fld_par )* }
     } else {
-        let fst_fld = &fld_par[0];
-        let res_fld = &fld_par[1..];
-        quote! {
-            ::sexpy::error::context(#context, ::sexpy::nom::sequence::tuple((
-                ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #fst_fld),
-                #( ::sexpy::nom::sequence::preceded(::sexpy::parsers::wordbreak0, #res_fld) ),*
-            )))
-        }
+        sequence_syn(&fld_par, &idents)
     };
 
-    // check if the enum takes arguments
-    let enum_constr = if var.fields.is_empty() {
-        quote! { #id::#name }
-    } else if let Fields::Named(_) = &var.fields {
-        // XXX(sam) I don't like this ^^^^^^^
-        quote! { #id::#name { #(#idents),* }  }
+    // label the variant unless the user gave an expectation for it
+    let field_syn = if var.fields.is_empty() || attrs.expect.is_some() {
+        field_syn
     } else {
-        quote! { #id::#name(#(#idents),*) }
+        let context = format!("Parsing {}", name);
+        quote! { ::sexpy::error::context(#context, #field_syn) }
     };
 
+    let enum_constr =
+        constructor_syn(quote! { #id::#name }, &var.fields, &idents);
+
     // apply attribute syntax changes and construct final parser
     let ts = attrs.apply(field_syn);
     quote! {
@@ -241,6 +537,34 @@ fn variant_parser(
     }
 }
 
+/// Generates a parser that runs `fields` one after the other and returns
+/// their results as a tuple, to be bound with `field_binder_syn(idents)`.
+/// Unlike `nom::sequence::tuple` this works for any number of fields.
+fn sequence_syn(fields: &[TokenStream], idents: &[Ident]) -> TokenStream {
+    // not visible to the user's code, so it can't clash with a field name
+    let rest = Ident::new("rest", Span::mixed_site());
+    quote! {
+        (|#rest: &'a str| {
+            #( let (#rest, #idents) = #fields(#rest)?; )*
+            Ok((#rest, (#(#idents),*)))
+        })
+    }
+}
+
+/// Generates the expression that builds `path`, a struct or enum variant
+/// with `fields`, out of the values bound to `idents`
+fn constructor_syn(
+    path: TokenStream,
+    fields: &Fields,
+    idents: &[Ident],
+) -> TokenStream {
+    match fields {
+        Fields::Named(_) => quote! { #path { #(#idents),* } },
+        Fields::Unnamed(_) => quote! { #path(#(#idents),*) },
+        Fields::Unit => path,
+    }
+}
+
 /// Helper function to generate the syntax for binding and deconstructing
 /// identifers that we get from calling parsers
 fn field_binder_syn(idents: &[Ident]) -> TokenStream {
@@ -0,0 +1,127 @@
+//! Detects enum variants that the derived parser can never reach.
+//!
+//! Variants are tried in declaration order and the first one that matches
+//! wins, so a variant is unreachable when every input it accepts is already
+//! taken by a variant declared before it.
+
+use crate::attrs::{Choice, FieldAttrs};
+use proc_macro_error::emit_error;
+use syn::{Ident, Variant};
+
+/// The parts of a variant that decide which inputs it matches
+pub struct Pattern<'a> {
+    ident: &'a Ident,
+    /// every spelling of the head, empty for headless variants
+    heads: Vec<String>,
+    surround: bool,
+    /// the generated parser of each field, compared textually
+    fields: Vec<String>,
+}
+
+impl<'a> Pattern<'a> {
+    /// The pattern of `var`, parsed with `attrs`
+    pub fn new(var: &'a Variant, attrs: &FieldAttrs) -> Self {
+        let heads = match &attrs.head {
+            Some(head) if !attrs.nohead => std::iter::once(head)
+                .chain(&attrs.aliases)
+                .cloned()
+                .collect(),
+            _ => vec![],
+        };
+        Pattern {
+            ident: &var.ident,
+            heads,
+            surround: attrs.surround,
+            fields: crate::field_parser(&var.fields)
+                .into_iter()
+                .map(|(fld, _)| fld.to_string())
+                .collect(),
+        }
+    }
+
+    /// The same pattern with its heads lowercased, for types whose
+    /// heads are case insensitive
+    pub fn lowercase(mut self) -> Self {
+        for head in &mut self.heads {
+            head.make_ascii_lowercase()
+        }
+        self
+    }
+
+    /// The pattern of a variant of a `keyword` enum, spelled `words`
+    pub fn keyword(var: &'a Variant, words: Vec<String>) -> Self {
+        Pattern {
+            ident: &var.ident,
+            heads: words,
+            surround: false,
+            fields: vec![],
+        }
+    }
+}
+
+/// Emits an error on every variant that is shadowed by one declared
+/// before it when the variants are picked by `choice`
+pub fn check(patterns: &[Pattern], choice: Choice) {
+    for (idx, later) in patterns.iter().enumerate() {
+        let found = patterns[..idx].iter().find_map(|earlier| {
+            Some((earlier, shadows(earlier, later, choice)?))
+        });
+        if let Some((earlier, reason)) = found {
+            emit_error!(
+                later.ident.span(),
+                "variant `{}` can never be parsed: {}", later.ident, reason;
+                note = earlier.ident.span() => "`{}` is declared here", earlier.ident
+            )
+        }
+    }
+}
+
+/// Why `earlier` matches every input that `later` does, if it does
+fn shadows(
+    earlier: &Pattern,
+    later: &Pattern,
+    choice: Choice,
+) -> Option<String> {
+    let same_head = earlier.heads.iter().find(|h| later.heads.contains(h));
+    if earlier.surround == later.surround
+        && earlier.fields == later.fields
+        && (same_head.is_some()
+            || earlier.heads.is_empty() && later.heads.is_empty())
+    {
+        return Some(format!(
+            "`{}` matches exactly the same inputs",
+            earlier.ident
+        ));
+    }
+
+    // only ordered choice commits to the first variant that matches
+    if choice != Choice::Ordered {
+        return None;
+    }
+
+    // the parser commits to a variant once its head matches
+    if let Some(head) = same_head.filter(|_| earlier.surround == later.surround)
+    {
+        return Some(format!(
+            "`{}` has the same head `{}` and is tried first",
+            earlier.ident, head
+        ));
+    }
+
+    // a bare variant succeeds as soon as its fields match, leaving
+    // the rest of the input to whatever comes after the enum
+    if earlier.heads.is_empty() && !earlier.surround {
+        let prefix = earlier.fields.is_empty()
+            || (later.heads.is_empty()
+                && !later.surround
+                && later.fields.starts_with(&earlier.fields));
+        if prefix {
+            return Some(format!(
+                "`{}` matches a prefix of it and is tried first",
+                earlier.ident
+            ));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,145 @@
+//! Comments kept from the input, for tools such as formatters and
+//! documentation generators that need more than the parsed values.
+//!
+//! With the `keep_comments` option, the comments in every run of whitespace
+//! that the parsers skip are remembered until they are attached to a value.
+//! A form can ask for the comments that came right before it, either with a
+//! `#[sexpy(comments)]` field of type `Vec<Comment>` or by being wrapped in a
+//! [`Commented`]:
+//!
+//! ```rust
+//! use sexpy::{Comment, Commented, ParseOptions, Sexpy};
+//!
+//! #[derive(Sexpy, Debug)]
+//! struct Port {
+//!     #[sexpy(comments)]
+//!     comments: Vec<Comment>,
+//!     name: String,
+//!     width: u64,
+//! }
+//!
+//! #[derive(Sexpy, Debug)]
+//! struct Module {
+//!     ports: Vec<Commented<Port>>,
+//! }
+//!
+//! let module = Module::parse_with(
+//!     "(module
+//!        ;;; the clock
+//!        (port clk 1)
+//!        (port data 32))",
+//!     &ParseOptions::new().keep_comments(true),
+//! )
+//! .unwrap();
+//! assert_eq!(module.ports[0].doc(), Some("the clock".to_string()));
+//! assert_eq!(module.ports[0].value.comments[0].to_string(), ";;; the clock");
+//! assert!(module.ports[1].comments.is_empty());
+//! ```
+//!
+//! Without the option, comment fields and `Commented` values are always
+//! empty and the parsers do no work to collect comments.
+//!
+//! sexpy has no printer, so re-emitting comments is left to the user's own
+//! printing code; the `Display` impls write comments back exactly as they
+//! were written in the input.
+
+use crate::error::SexpyError;
+use crate::parsers::{attach_comments, leading_comments};
+use crate::Sexpy;
+use nom::IResult;
+use std::fmt;
+use std::ops::Range;
+
+/// The syntax a [`Comment`] was written with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommentKind {
+    /// a `;` comment that runs to the end of the line
+    Line,
+    /// a `#| ... |#` block comment
+    Block,
+    /// a `#;` comment that comments out the s-expression after it
+    Datum,
+}
+
+/// A comment as it was written in the input
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    /// the text of the comment, including the characters that start
+    /// and end it but not the newline after a line comment
+    pub text: String,
+    /// byte range of the input the comment covers
+    pub span: Range<usize>,
+}
+
+impl Comment {
+    /// The text of a doc comment, which is a line comment started with the
+    /// comment character three times, such as `;;; text`. One space after
+    /// the start is removed.
+    pub fn doc(&self) -> Option<&str> {
+        if self.kind != CommentKind::Line {
+            return None;
+        }
+        let c = self.text.chars().next()?;
+        let start = c.to_string().repeat(3);
+        let text = self.text.strip_prefix(&start)?;
+        Some(text.strip_prefix(' ').unwrap_or(text))
+    }
+}
+
+impl fmt::Display for Comment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// The text of the doc comments among `comments`, one line each,
+/// or `None` if there are none
+pub fn doc(comments: &[Comment]) -> Option<String> {
+    let lines: Vec<&str> = comments.iter().filter_map(Comment::doc).collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// A `T` along with the comments written right before it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Commented<T> {
+    pub comments: Vec<Comment>,
+    pub value: T,
+}
+
+impl<T> Commented<T> {
+    /// The text of the doc comments before the value. See [`doc`].
+    pub fn doc(&self) -> Option<String> {
+        doc(&self.comments)
+    }
+}
+
+/// Parses `T`, keeping the comments that were skipped before it
+impl<T: Sexpy> Sexpy for Commented<T> {
+    fn sexp_parse(input: &str) -> IResult<&str, Self, SexpyError<&str>>
+    where
+        Self: Sized,
+    {
+        let comments = leading_comments(input);
+        let (next, value) = attach_comments(input, T::sexp_parse)?;
+        Ok((next, Commented { comments, value }))
+    }
+}
+
+/// Writes the comments, each line comment on its own line, followed by
+/// the value
+impl<T: fmt::Display> fmt::Display for Commented<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for comment in &self.comments {
+            match comment.kind {
+                CommentKind::Line => writeln!(f, "{}", comment)?,
+                _ => write!(f, "{} ", comment)?,
+            }
+        }
+        write!(f, "{}", self.value)
+    }
+}
@@ -0,0 +1,444 @@
+//! Structured diagnostics and a renderer that turns them into
+//! human readable snippets of the source.
+//!
+//! A [`Diagnostic`] only stores byte offsets into the parsed input, so it
+//! does not borrow the input and can outlive it. Use a [`Renderer`] to
+//! display it against the source it came from:
+//!
+//! ```rust
+//! use sexpy::{diagnostic::Renderer, Sexpy};
+//!
+//! let input = "(foo bar";
+//! let diag = u64::parse_diagnostic(input).unwrap_err();
+//! let text = Renderer::plain().filename("foo.sexp").render(input, &diag);
+//! assert!(text.contains("--> foo.sexp:1:1"));
+//! ```
+
+use std::ops::Range;
+
+/// How bad a [`Diagnostic`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+/// A secondary message attached to a range of the input,
+/// such as "form opened here"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    /// byte range of the input this label points at
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+/// A message about a range of the input, along with any secondary labels
+/// and notes that help explain it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// byte range of the input the diagnostic is about
+    pub span: Range<usize>,
+    /// secondary labels, rendered underneath the lines they point at
+    pub labels: Vec<Label>,
+    /// free form notes, rendered after the snippet
+    pub notes: Vec<String>,
+    /// what the parser would have accepted at `span`, if known
+    pub expected: Vec<String>,
+    /// the contexts that were being parsed when the diagnostic was
+    /// produced, innermost first
+    pub context: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Make an error about `span`
+    pub fn error<S: Into<String>>(span: Range<usize>, message: S) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+            expected: vec![],
+            context: vec![],
+        }
+    }
+
+    /// Make a warning about `span`
+    pub fn warning<S: Into<String>>(span: Range<usize>, message: S) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(span, message)
+        }
+    }
+
+    /// Attach a secondary label to the diagnostic
+    pub fn with_label<S: Into<String>>(
+        mut self,
+        span: Range<usize>,
+        message: S,
+    ) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Attach a note to the diagnostic
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+#[cfg(feature = "json")]
+impl Diagnostic {
+    /// Serialize the diagnostic to JSON. `source` is the input the
+    /// diagnostic points into and is used to compute line and column
+    /// numbers; `file` is reported as is. The shape is:
+    ///
+    /// ```json
+    /// {
+    ///   "file": "ports.sexp",
+    ///   "severity": "error",
+    ///   "message": "expected ')', found '2'",
+    ///   "range": {
+    ///     "start": { "offset": 15, "line": 2, "column": 6 },
+    ///     "end": { "offset": 16, "line": 2, "column": 7 }
+    ///   },
+    ///   "expected": ["')'"],
+    ///   "context": ["closing paren"],
+    ///   "labels": [{ "range": { ... }, "message": "form opened here" }],
+    ///   "notes": []
+    /// }
+    /// ```
+    ///
+    /// Lines and columns are 1-based, columns count characters and
+    /// offsets count bytes. `file` is `null` when not given.
+    pub fn to_json(&self, source: &str, file: Option<&str>) -> String {
+        serde_json::to_string(&self.to_json_value(source, file))
+            .expect("diagnostics always serialize")
+    }
+
+    /// Like `to_json` but returns a `serde_json::Value`, which is useful
+    /// when the diagnostic is embedded in a larger document
+    pub fn to_json_value(
+        &self,
+        source: &str,
+        file: Option<&str>,
+    ) -> serde_json::Value {
+        let json = json::Diagnostic {
+            file,
+            severity: self.severity.as_str(),
+            message: &self.message,
+            range: json::Range::new(source, &self.span),
+            expected: &self.expected,
+            context: &self.context,
+            labels: self
+                .labels
+                .iter()
+                .map(|l| json::Label {
+                    range: json::Range::new(source, &l.span),
+                    message: &l.message,
+                })
+                .collect(),
+            notes: &self.notes,
+        };
+        serde_json::to_value(json).expect("diagnostics always serialize")
+    }
+}
+
+/// The serialized shape of a diagnostic. Kept separate from `Diagnostic`
+/// so that the JSON format stays stable when the Rust types change.
+#[cfg(feature = "json")]
+mod json {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    pub struct Diagnostic<'a> {
+        pub file: Option<&'a str>,
+        pub severity: &'static str,
+        pub message: &'a str,
+        pub range: Range,
+        pub expected: &'a [String],
+        pub context: &'a [String],
+        pub labels: Vec<Label<'a>>,
+        pub notes: &'a [String],
+    }
+
+    #[derive(Serialize)]
+    pub struct Label<'a> {
+        pub range: Range,
+        pub message: &'a str,
+    }
+
+    #[derive(Serialize)]
+    pub struct Range {
+        pub start: Position,
+        pub end: Position,
+    }
+
+    #[derive(Serialize)]
+    pub struct Position {
+        pub offset: usize,
+        pub line: usize,
+        pub column: usize,
+    }
+
+    impl Range {
+        pub fn new(source: &str, span: &std::ops::Range<usize>) -> Self {
+            Range {
+                start: Position::new(source, span.start),
+                end: Position::new(source, span.end),
+            }
+        }
+    }
+
+    impl Position {
+        fn new(source: &str, offset: usize) -> Self {
+            let (line, column) = super::line_col(source, offset);
+            Position {
+                offset,
+                line: line + 1,
+                column: column + 1,
+            }
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders a [`Diagnostic`] against the source it refers to.
+/// Lines and columns are 1-based; columns count characters, not bytes.
+#[derive(Clone, Debug, Default)]
+pub struct Renderer {
+    filename: Option<String>,
+    color: bool,
+    show_context: bool,
+    line_offset: usize,
+    column_offset: usize,
+}
+
+impl Renderer {
+    /// A renderer that produces plain text, suitable for tests and logs
+    pub fn plain() -> Self {
+        Renderer::default()
+    }
+
+    /// A renderer that highlights its output with ANSI color codes
+    pub fn colored() -> Self {
+        Renderer {
+            color: true,
+            ..Renderer::default()
+        }
+    }
+
+    /// Set the file name displayed in the location line
+    pub fn filename<S: Into<String>>(mut self, name: S) -> Self {
+        self.filename = Some(name.into());
+        self
+    }
+
+    /// Turn ANSI colors on or off
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// When true, also render the stack of contexts that were being parsed
+    pub fn show_context(mut self, show: bool) -> Self {
+        self.show_context = show;
+        self
+    }
+
+    /// Number the lines as if `lines` lines came before the source, for
+    /// a source that is only part of a larger input
+    pub fn line_offset(mut self, lines: usize) -> Self {
+        self.line_offset = lines;
+        self
+    }
+
+    /// Number the columns of the first line as if `columns` characters came
+    /// before the source on that line, for a source that starts mid-line
+    pub fn column_offset(mut self, columns: usize) -> Self {
+        self.column_offset = columns;
+        self
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Render `diag` using the snippets of `source` it points at
+    pub fn render(&self, source: &str, diag: &Diagnostic) -> String {
+        let lines: Vec<&str> = source
+            .split('\n')
+            .map(|l| l.strip_suffix('\r').unwrap_or(l))
+            .collect();
+        let (line, mut col) = line_col(source, diag.span.start);
+        if line == 0 {
+            col += self.column_offset;
+        }
+        let sev = diag.severity;
+
+        // collect underlines for each line: (line, start col, end col, primary, label)
+        let mut marks: Vec<(usize, usize, usize, bool, &str)> = vec![];
+        add_marks(&mut marks, source, &diag.span, true, "");
+        for label in &diag.labels {
+            add_marks(&mut marks, source, &label.span, false, &label.message);
+        }
+        let mut shown: Vec<usize> = marks.iter().map(|m| m.0).collect();
+        shown.sort_unstable();
+        shown.dedup();
+
+        let first = self.line_offset + 1;
+        let width = shown.last().map_or(1, |l| (l + first).to_string().len());
+        let gutter = self.paint(BLUE, &format!("{} |", " ".repeat(width)));
+        let mut result = String::new();
+
+        result += &self.paint(sev.color(), sev.as_str());
+        result += &self.paint(BOLD, &format!(": {}", diag.message));
+        result += "\n";
+        result += &format!(
+            "{}{} {}:{}:{}\n",
+            " ".repeat(width),
+            self.paint(BLUE, "-->"),
+            self.filename.as_deref().unwrap_or("<input>"),
+            line + first,
+            col + 1
+        );
+        result += &gutter;
+        result += "\n";
+
+        let mut prev: Option<usize> = None;
+        for l in shown {
+            if let Some(p) = prev {
+                if l > p + 1 {
+                    result += &self.paint(BLUE, "...");
+                    result += "\n";
+                }
+            }
+            prev = Some(l);
+
+            let num = format!("{:>width$} |", l + first, width = width);
+            result += &self.paint(BLUE, &num);
+            result += " ";
+            result += lines.get(l).unwrap_or(&"");
+            result += "\n";
+
+            for (_, start, end, primary, label) in
+                marks.iter().filter(|m| m.0 == l)
+            {
+                let (ch, style) = if *primary {
+                    ('^', sev.color())
+                } else {
+                    ('-', BLUE)
+                };
+                let underline = ch.to_string().repeat(end - start);
+                let mut text = format!("{}{}", " ".repeat(*start), underline);
+                if !label.is_empty() {
+                    text += " ";
+                    text += label;
+                }
+                result += &format!("{} {}\n", gutter, self.paint(style, &text));
+            }
+        }
+
+        for note in &diag.notes {
+            result += &format!(
+                "{} {} {}\n",
+                " ".repeat(width),
+                self.paint(BLUE, "="),
+                note
+            );
+        }
+
+        if self.show_context {
+            for ctx in &diag.context {
+                result += &format!(
+                    "{} {} in {}\n",
+                    " ".repeat(width),
+                    self.paint(BLUE, "="),
+                    ctx
+                );
+            }
+        }
+
+        result
+    }
+}
+
+/// Converts a byte offset into a 0-based line and character column
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count())
+}
+
+/// Splits `span` into one underline per line it covers. Empty spans are
+/// widened to a single caret so that positions such as <eof> are visible.
+fn add_marks<'a>(
+    marks: &mut Vec<(usize, usize, usize, bool, &'a str)>,
+    source: &str,
+    span: &Range<usize>,
+    primary: bool,
+    label: &'a str,
+) {
+    let (start_line, start_col) = line_col(source, span.start);
+    let (mut end_line, mut end_col) =
+        line_col(source, span.end.max(span.start));
+
+    // a span ending just after a newline does not cover the next line
+    if end_line > start_line && end_col == 0 {
+        end_line -= 1;
+        end_col = line_len(source, end_line);
+    }
+
+    for l in start_line..=end_line {
+        let from = if l == start_line { start_col } else { 0 };
+        let to = if l == end_line {
+            end_col
+        } else {
+            line_len(source, l).max(from)
+        };
+        // only the last line of a span carries the label
+        let text = if l == end_line { label } else { "" };
+        marks.push((l, from, to.max(from + 1), primary, text));
+    }
+}
+
+/// Number of characters on the 0-based line `line` of `source`
+fn line_len(source: &str, line: usize) -> usize {
+    source
+        .split('\n')
+        .nth(line)
+        .map_or(0, |s| s.trim_end_matches('\r').chars().count())
+}
@@ -1,8 +1,8 @@
+use crate::diagnostic::{Diagnostic, Renderer};
 use nom::{
     error::{ErrorKind, ParseError},
     Err, IResult,
 };
-use std::iter::repeat;
 
 #[derive(Debug)]
 pub struct SexpyError<Input> {
@@ -14,17 +14,44 @@ pub struct SexpyError<Input> {
 pub enum SexpyErrorKind {
     /// static string added by the `context` function
     Context(&'static str),
+    /// human readable expectation added by the `expect` function
+    Expected(&'static str),
     /// indicates which character was expected by the `char` function
     Char(char),
     /// indicates which word was expected by the `word` function
     Word(String),
+    /// indicates that the `word` function found `found` where one of the
+    /// keywords in `expected` was required
+    Keyword {
+        found: String,
+        expected: Vec<String>,
+    },
     /// indicates an error occurred while parsing a number
     Number,
+    /// the delimiter opened at this position was never closed
+    Unclosed(char),
+    /// a value spanning `len` bytes was parsed but rejected by a
+    /// validation function with `message`
+    Invalid { message: String, len: usize },
+    /// found a closing delimiter that does not close anything
+    Unmatched(char),
+    /// more than one of the alternatives in `matches` accepted the input,
+    /// the longest of them spanning `len` bytes
+    Ambiguous {
+        matches: Vec<&'static str>,
+        len: usize,
+    },
+    /// the block comment opened here is never closed
+    UnterminatedComment,
+    /// the form starting here is nested deeper than the limit
+    TooDeep(usize),
+    /// a secondary message pointing at this position
+    Label(&'static str),
     /// error kind given by various nom parsers
     Nom(ErrorKind),
 }
 
-impl<Input> ParseError<Input> for SexpyError<Input> {
+impl<Input: PartialEq> ParseError<Input> for SexpyError<Input> {
     fn from_error_kind(input: Input, kind: ErrorKind) -> Self {
         SexpyError {
             errors: vec![(input, SexpyErrorKind::Nom(kind))],
@@ -46,6 +73,37 @@ impl<Input> ParseError<Input> for SexpyError<Input> {
         other.errors.push((input, SexpyErrorKind::Context(ctx)));
         other
     }
+
+    /// Called by `alt` when two alternatives fail. If both failed on
+    /// a keyword at the same position, the expected keywords are merged
+    /// so that the error can list (and suggest) every keyword that would
+    /// have been accepted. A keyword error is also kept over any other error
+    /// at the same position, so that alternatives without a keyword in
+    /// between don't lose the keywords before them. Otherwise the last error
+    /// is kept.
+    fn or(self, mut other: Self) -> Self {
+        match (self.errors.first(), other.errors.first_mut()) {
+            (
+                Some((i1, SexpyErrorKind::Keyword { expected: exp1, .. })),
+                Some((i2, SexpyErrorKind::Keyword { expected: exp2, .. })),
+            ) if i1 == i2 => {
+                let mut merged = exp1.clone();
+                for e in exp2.drain(..) {
+                    if !merged.contains(&e) {
+                        merged.push(e);
+                    }
+                }
+                *exp2 = merged;
+                other
+            }
+            (Some((i1, SexpyErrorKind::Keyword { .. })), Some((i2, _)))
+                if i1 == i2 =>
+            {
+                self
+            }
+            _ => other,
+        }
+    }
 }
 
 impl<Input> SexpyError<Input> {
@@ -56,6 +114,78 @@ impl<Input> SexpyError<Input> {
         }
     }
 
+    /// Make a `SexpyErrorKind::Keyword` from an Input, the word that was
+    /// found and the keyword that was expected
+    pub fn from_keyword(input: Input, found: String, expected: &str) -> Self {
+        SexpyError {
+            errors: vec![(
+                input,
+                SexpyErrorKind::Keyword {
+                    found,
+                    expected: vec![expected.to_string()],
+                },
+            )],
+        }
+    }
+
+    /// Record that the delimiter `open`, found at `input`, was
+    /// never closed because of `other`
+    pub fn unclosed(input: Input, open: char, mut other: Self) -> Self {
+        other.errors.push((input, SexpyErrorKind::Unclosed(open)));
+        other
+    }
+
+    /// Make a `SexpyErrorKind::Unmatched` from an Input and the closing delimiter
+    pub fn unmatched(input: Input, close: char) -> Self {
+        SexpyError {
+            errors: vec![(input, SexpyErrorKind::Unmatched(close))],
+        }
+    }
+
+    /// Make a `SexpyErrorKind::Invalid` from the Input the rejected value was
+    /// parsed from, its length and the reason it was rejected
+    pub fn invalid(input: Input, len: usize, message: String) -> Self {
+        SexpyError {
+            errors: vec![(input, SexpyErrorKind::Invalid { message, len })],
+        }
+    }
+
+    /// Make a `SexpyErrorKind::Ambiguous` from the Input the alternatives
+    /// started at, the longest length they parsed and their names
+    pub fn ambiguous(
+        input: Input,
+        len: usize,
+        matches: Vec<&'static str>,
+    ) -> Self {
+        SexpyError {
+            errors: vec![(input, SexpyErrorKind::Ambiguous { matches, len })],
+        }
+    }
+
+    /// Make a `SexpyErrorKind::UnterminatedComment` from the Input
+    /// the comment starts at
+    pub fn unterminated_comment(input: Input) -> Self {
+        SexpyError {
+            errors: vec![(input, SexpyErrorKind::UnterminatedComment)],
+        }
+    }
+
+    /// Make a `SexpyErrorKind::TooDeep` from the Input of the form that went
+    /// over the nesting limit and the limit
+    pub fn too_deep(input: Input, limit: usize) -> Self {
+        SexpyError {
+            errors: vec![(input, SexpyErrorKind::TooDeep(limit))],
+        }
+    }
+
+    /// Whether this error is from going over the nesting limit, which
+    /// no alternative can recover from
+    pub fn is_too_deep(&self) -> bool {
+        self.errors
+            .iter()
+            .any(|(_, kind)| matches!(kind, SexpyErrorKind::TooDeep(_)))
+    }
+
     /// Make a `SexpyErrorKind::Number` from an Input
     pub fn number(input: Input) -> Self {
         SexpyError {
@@ -69,32 +199,102 @@ impl SexpyError<&str> {
     /// Only shows the topmost parsing error. Use `convert_error_verbose`
     /// to get the whole error stack
     pub fn convert_error(&self, input: &str) -> String {
-        if self.errors.is_empty() {
-            panic!("No errors found")
-        } else {
-            format_error(input, 0, &self.errors[0])
-        }
+        Renderer::plain().render(input, &self.diagnostic(input))
     }
 
     /// Converts a `SexpyError` into a formated string.
-    /// Shows the entire error stack. Use `convert_error` to show
-    /// only the topmost error
-    #[allow(unused)]
+    /// Also shows the stack of contexts that were being parsed.
+    /// Use `convert_error` to show only the topmost error
     pub fn convert_error_verbose(&self, input: &str) -> String {
-        let mut result = String::new();
+        Renderer::plain()
+            .show_context(true)
+            .render(input, &self.diagnostic(input))
+    }
+
+    /// Converts a `SexpyError` into a `Diagnostic` pointing into `input`.
+    /// The topmost error becomes the message, an unclosed delimiter becomes a
+    /// "form opened here" label, and the rest of the stack becomes the context.
+    pub fn diagnostic(&self, input: &str) -> Diagnostic {
+        let (substring, kind) = match self.errors.first() {
+            Some(e) => e,
+            None => panic!("No errors found"),
+        };
+        let start = offset(input, substring);
+        let len = match kind {
+            SexpyErrorKind::Word(w) => w.len(),
+            SexpyErrorKind::Keyword { found, .. } => found.len(),
+            SexpyErrorKind::Invalid { len, .. } => *len,
+            SexpyErrorKind::Ambiguous { len, .. } => *len,
+            SexpyErrorKind::UnterminatedComment => 2,
+            SexpyErrorKind::Number => substring
+                .find(|c: char| !(c.is_ascii_digit() || c == '-'))
+                .unwrap_or(substring.len()),
+            _ => substring.chars().next().map_or(0, char::len_utf8),
+        };
+
+        let mut diag =
+            Diagnostic::error(start..start + len, message(substring, kind));
+        diag.expected = match kind {
+            SexpyErrorKind::Char(c) => vec![format!("'{}'", c)],
+            SexpyErrorKind::Keyword { expected, .. } => expected.clone(),
+            SexpyErrorKind::Number => vec!["number".to_string()],
+            _ => vec![],
+        };
+
+        // a user given expectation at the position of the error
+        // replaces the lower level message
+        let expectation =
+            self.errors[1..].iter().find_map(|(sub, k)| match k {
+                SexpyErrorKind::Expected(msg)
+                    if offset(input, sub) == start =>
+                {
+                    Some(*msg)
+                }
+                _ => None,
+            });
+        if let Some(msg) = expectation {
+            let len = match token(substring) {
+                "" => substring.chars().next().map_or(0, char::len_utf8),
+                t => t.len(),
+            };
+            diag.message =
+                format!("expected {}, found {}", msg, found(substring));
+            diag.span = start..start + len;
+            diag.expected = vec![msg.to_string()];
+        }
 
-        for (i, error) in self.errors.iter().enumerate() {
-            result += &format_error(input, i, error);
+        let mut opened = matches!(kind, SexpyErrorKind::Unclosed(_));
+        for (sub, kind) in &self.errors[1..] {
+            match kind {
+                SexpyErrorKind::Unclosed(c) if !opened => {
+                    opened = true;
+                    let at = offset(input, sub);
+                    diag = diag
+                        .with_label(at..at + c.len_utf8(), "form opened here");
+                }
+                SexpyErrorKind::Label(msg) => {
+                    let at = offset(input, sub);
+                    let len = sub.chars().next().map_or(0, char::len_utf8);
+                    diag = diag.with_label(at..at + len, *msg);
+                }
+                SexpyErrorKind::Context(ctx) => {
+                    diag.context.push(ctx.to_string())
+                }
+                SexpyErrorKind::Expected(msg) if Some(*msg) != expectation => {
+                    diag.context.push(msg.to_string())
+                }
+                _ => (),
+            }
         }
 
-        result
+        diag
     }
 }
 
 /// create a new error from an input position, a static string and an existing error.
 /// This is used mainly in the [context] combinator, to add user friendly information
 /// to errors when backtracking through a parse tree
-pub fn context<I: Clone, E: ParseError<I>, F, O>(
+pub fn context<I: Clone + PartialEq, E: ParseError<I>, F, O>(
     context: &'static str,
     f: F,
 ) -> impl Fn(I) -> IResult<I, O, E>
@@ -111,6 +311,32 @@ where
     }
 }
 
+/// Like [context], but `expectation` describes what the parser expects to find
+/// in human readable terms, such as "a port width in bits". When parsing fails
+/// right where the expectation starts, error messages use it in place of the
+/// lower level error. Otherwise it is shown as a context.
+pub fn expect<I: Clone, F, O>(
+    expectation: &'static str,
+    f: F,
+) -> impl Fn(I) -> IResult<I, O, SexpyError<I>>
+where
+    F: Fn(I) -> IResult<I, O, SexpyError<I>>,
+{
+    move |i: I| {
+        let add = |mut e: SexpyError<I>| {
+            e.errors
+                .push((i.clone(), SexpyErrorKind::Expected(expectation)));
+            e
+        };
+        match f(i.clone()) {
+            Ok(o) => Ok(o),
+            Err(Err::Incomplete(i)) => Err(Err::Incomplete(i)),
+            Err(Err::Error(e)) => Err(Err::Error(add(e))),
+            Err(Err::Failure(e)) => Err(Err::Failure(add(e))),
+        }
+    }
+}
+
 fn offset(first: &str, second: &str) -> usize {
     let fst = first.as_ptr();
     let snd = second.as_ptr();
@@ -118,106 +344,128 @@ fn offset(first: &str, second: &str) -> usize {
     snd as usize - fst as usize
 }
 
-fn format_error(input: &str, num: usize, e: &(&str, SexpyErrorKind)) -> String {
-    let lines: Vec<_> = input.lines().map(String::from).collect();
-    let (substring, kind) = e;
-    let mut offset = offset(input, substring);
-    let mut result = String::new();
-
-    if lines.is_empty() {
-        match kind {
-            SexpyErrorKind::Char(c) => {
-                result +=
-                    &format!("{}: expected '{}', got empty input\n\n", num, c);
-            }
-            SexpyErrorKind::Word(_w) => {
-                result += &format!(
-                    "{}: expected a keyword, got empty input\n\n",
-                    num
-                );
-            }
-            SexpyErrorKind::Number => {
-                result +=
-                    &format!("{}: expected a number, got empty input\n\n", num);
-            }
-            SexpyErrorKind::Context(s) => {
-                result += &format!("{}: in {}, got empty input\n\n", num, s);
-            }
-            SexpyErrorKind::Nom(e) => {
-                result += &format!("{}: in {:?}, got empty input\n\n", num, e);
-            }
+/// The message describing a single error of kind `kind` found at `rest`
+fn message(rest: &str, kind: &SexpyErrorKind) -> String {
+    let found = match rest.chars().next() {
+        Some(x) => format!("'{}'", x),
+        None => "<eof>".to_string(),
+    };
+    match kind {
+        SexpyErrorKind::Char(c) => format!("expected '{}', found {}", c, found),
+        SexpyErrorKind::Word(w) => {
+            format!("expected a keyword, found \"{}\"", w)
         }
-    } else {
-        let mut line = 0;
-        let mut column = 0;
-
-        for (j, l) in lines.iter().enumerate() {
-            if offset <= l.len() {
-                line = j;
-                column = offset;
-                break;
-            } else {
-                offset = offset - l.len() - 1;
-            }
+        SexpyErrorKind::Keyword { found, expected } => {
+            keyword_message(rest, found, expected)
+        }
+        SexpyErrorKind::Number => "unable to parse number".to_string(),
+        SexpyErrorKind::Invalid { message, .. } => message.clone(),
+        SexpyErrorKind::Unclosed(c) => format!("unclosed '{}'", c),
+        SexpyErrorKind::Unmatched(c) => {
+            format!("unexpected '{}' with no matching opening delimiter", c)
+        }
+        SexpyErrorKind::Ambiguous { matches, .. } => {
+            let quoted: Vec<String> =
+                matches.iter().map(|m| format!("`{}`", m)).collect();
+            let (last, rest) = quoted.split_last().expect("two matches");
+            format!(
+                "ambiguous input, it can be parsed as {} or {}",
+                rest.join(", "),
+                last
+            )
+        }
+        SexpyErrorKind::UnterminatedComment => {
+            "unterminated block comment".to_string()
+        }
+        SexpyErrorKind::TooDeep(limit) => {
+            format!("nesting too deep, the limit is {} levels", limit)
         }
+        SexpyErrorKind::Label(s) => s.to_string(),
+        SexpyErrorKind::Context(s) => format!("in {}, found {}", s, found),
+        SexpyErrorKind::Expected(s) => {
+            format!("expected {}, found {}", s, found)
+        }
+        SexpyErrorKind::Nom(e) => format!("in {:?}, found {}", e, found),
+    }
+}
 
-        match kind {
-            SexpyErrorKind::Char(c) => {
-                result += &format!("{}: at line {}:\n", num, line);
-                result += &lines[line];
-                result += "\n";
+/// The word at the start of `rest`, up to the next word boundary
+fn token(rest: &str) -> &str {
+    let end = rest
+        .find(|c: char| " ()[]{}\n\t\r;".contains(c))
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
 
-                if column > 0 {
-                    result += &repeat(' ').take(column).collect::<String>();
-                }
-                result += "^\n";
-                let found = match substring.chars().next() {
-                    Some(x) => format!("'{}'", x),
-                    None => "<eof>".to_string(),
-                };
-                result += &format!("expected '{}', found {}\n\n", c, found);
-            }
-            SexpyErrorKind::Word(w) => {
-                result += &format!("{}: at line {}:\n", num, line);
-                result += &lines[line];
-                result += "\n";
+/// Describes what was found at the start of `rest`
+fn found(rest: &str) -> String {
+    match (token(rest), rest.chars().next()) {
+        (_, None) => "<eof>".to_string(),
+        ("", Some(c)) => format!("'{}'", c),
+        (word, _) => format!("`{}`", word),
+    }
+}
 
-                if column > 0 {
-                    result += &repeat(' ').take(column).collect::<String>();
-                }
-                result += "^\n";
-                result += &format!("expected a keyword, found \"{}\"\n\n", w);
-            }
-            SexpyErrorKind::Number => {
-                result += &format!("{}: at line {}:\n", num, line);
-                result += &lines[line];
-                result += "\n";
+/// Formats a list of keywords as "`a`" or "one of `a`, `b`"
+fn one_of(expected: &[String]) -> String {
+    let quoted: Vec<String> =
+        expected.iter().map(|e| format!("`{}`", e)).collect();
+    if quoted.len() == 1 {
+        quoted[0].clone()
+    } else {
+        format!("one of {}", quoted.join(", "))
+    }
+}
 
-                if column > 0 {
-                    result += &repeat(' ').take(column).collect::<String>();
-                }
-                result += "^\n";
-                result += "unable to parse number\n\n";
-            }
-            SexpyErrorKind::Context(s) => {
-                result += &format!("{}: at line {}, in {}:\n", num, line, s);
-                result += &lines[line];
-                result += "\n";
-                if column > 0 {
-                    result += &repeat(' ').take(column).collect::<String>();
-                }
-                result += "^\n\n";
-            }
-            SexpyErrorKind::Nom(e) => {
-                result += &format!("{}: at line {}, in {:?}:\n", num, line, e);
-                result += &lines[line];
-                result += "\n";
-                if column > 0 {
-                    result += &repeat(' ').take(column).collect::<String>();
-                }
-                result += "^\n\n";
-            }
+/// Builds the message for a `SexpyErrorKind::Keyword` error, suggesting
+/// the closest expected keyword when `found` looks like a misspelling of it
+fn keyword_message(rest: &str, found: &str, expected: &[String]) -> String {
+    if found.is_empty() {
+        let got = match rest.chars().next() {
+            Some(x) => format!("'{}'", x),
+            None => "<eof>".to_string(),
+        };
+        return format!("expected {}, found {}", one_of(expected), got);
+    }
+
+    match suggest(found, expected) {
+        Some(s) => {
+            format!("unknown head `{}`, did you mean `{}`?", found, s)
         }
+        None => format!("expected {}, found `{}`", one_of(expected), found),
     }
-    result
+}
+
+/// Finds the candidate closest to `word` by edit distance. Only candidates
+/// that are a plausible misspelling (at most a third of the characters
+/// differ, and at least one) are considered.
+pub fn suggest<'a, S: AsRef<str>>(
+    word: &str,
+    candidates: &'a [S],
+) -> Option<&'a str> {
+    let max_dist = std::cmp::max(1, word.chars().count() / 3);
+    candidates
+        .iter()
+        .map(|c| (edit_distance(word, c.as_ref()), c.as_ref()))
+        .filter(|(d, _)| *d <= max_dist)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance between two strings, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let subst = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = subst.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[b.len()]
 }
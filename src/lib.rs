@@ -17,7 +17,8 @@ This matches `foo cactus 20` but not `foo big cactus`.
 ### Structs
 The default parser generated for a `struct` type uses a lowercased version of the struct
 name as the head and the types of the fields as arguments. It parses the head pattern surrounded
-by parentheses, brackets, or curly braces.
+by parentheses or brackets. Other delimiters, such as curly braces, can be allowed with
+[`ParseOptions::delimiters`].
 
 For example, consider the following:
 ```rust,ignore
@@ -31,10 +32,13 @@ This generates a parser for patterns of the form `(port <string> <u64>)`.
 `(port foo 10)` is parsed into `Port { name: "foo".to_string(), width: 10 }`
 and `port foo 10`, `(port 10 foo)` both fail to parse.
 
+Tuple structs and unit structs work the same way, with the same attributes:
+`struct Pair(String, u64)` parses `(pair <string> <u64>)` and `struct Nil;` parses `(nil)`.
+
 ### Enums
 For enums, a parser is generated for each case in the enum. By default, each parser
 uses the enum name as the head and the variant arguments as the pattern arguments.
-Each parser matches the pattern surrounded in parentheses, brackets, or curly braces.
+Each parser matches the pattern surrounded in parentheses or brackets.
 
 For example, consider the following enum definition:
 ```rust,ignore
@@ -70,10 +74,91 @@ enum Plant {
 }
 ```
 
+When variants have heads, the derived parser reads the head word once and jumps to the
+variant with that head instead of trying every variant in turn, so enums with many headed
+variants stay fast. Variants without a head, or with `surround`, are still tried in declaration
+order around the matching variant, so the result is the same as trying them all in order.
+
+Enums whose variants have no fields can be parsed from bare words with the `keyword`
+attribute. Each variant matches its lowercased name, or its `head` if one is given, along with
+any `alias` of it.
+```rust,ignore
+#[derive(Sexpy)]
+#[sexpy(keyword)]
+enum Op {
+  Add,                          // parses `add`
+  Sub,                          // parses `sub`
+  #[sexpy(head = "shift-left")]
+  Shl,                          // parses `shift-left`
+}
+```
+
 ### Caveats
-It is possible to derive two parsers that parse the exact same pattern. At the moment,
-`Sexpy` does nothing to detect and prevent this. It is up to the programmer to resolve
-these conflicts. The parsing options should make it easy to resolve them.
+Variants are tried in the order they are declared and the first one that matches wins, so
+it is possible to declare a variant that can never be parsed. With `#[sexpy(deny_unreachable)]`
+on the enum, the derive reports an error on such a variant when it can tell from the attributes
+and field types alone:
+ - it has the same head (or alias) as a variant declared before it, because the parser commits
+   to a variant once its head matches
+ - it has no head and the same field types as a variant declared before it
+ - a variant without a head or surround declared before it matches a prefix of its pattern,
+   such as `Short(u64)` before `Long(u64, u64)`
+
+So each of these enums fails to compile:
+```rust,compile_fail
+# use sexpy::Sexpy;
+#[derive(Sexpy)]
+#[sexpy(deny_unreachable)]
+enum Plant {
+  #[sexpy(head = "palm")]
+  Palm(u64),
+  #[sexpy(head = "palm")]     // error: `Palm` has the same head `palm` and is tried first
+  Date(String),
+}
+```
+```rust,compile_fail
+# use sexpy::Sexpy;
+#[derive(Sexpy)]
+#[sexpy(deny_unreachable)]
+enum Plant {
+  Palm(String, u64),
+  Cactus(String, u64),        // error: `Palm` matches exactly the same inputs
+}
+```
+```rust,compile_fail
+# use sexpy::Sexpy;
+#[derive(Sexpy)]
+#[sexpy(deny_unreachable)]
+enum Span {
+  Short(u64),
+  Long(u64, u64),             // error: `Short` matches a prefix of it and is tried first
+}
+```
+
+With `choice = "longest"` or `choice = "unique"` every variant is tried, so only variants that
+match exactly the same inputs are reported:
+```rust
+# use sexpy::Sexpy;
+#[derive(Sexpy)]
+#[sexpy(deny_unreachable, choice = "longest")]
+enum Span {
+  Short(u64),
+  Long(u64, u64),             // fine, the longest match wins
+}
+```
+```rust,compile_fail
+# use sexpy::Sexpy;
+#[derive(Sexpy)]
+#[sexpy(deny_unreachable, choice = "unique")]
+enum Plant {
+  Palm(String, u64),
+  Cactus(String, u64),        // error: `Palm` matches exactly the same inputs
+}
+```
+
+Overlaps that depend on what a type accepts, for example a `String` field matching the head of a
+later variant, are not detected. It is up to the programmer to resolve these conflicts. The parsing
+options should make it easy to resolve them.
 
 ### Options
 You can modify the pattern the derived parser matches by specifying some attributes.
@@ -94,14 +179,32 @@ All attributes are specified in a comma separated list in like so:
 `#[sexpy(attr = val, attr, ...)]`
 Arguments are taken in the form `<attr> = <val>`. For example when providing a head, which takes a
 string argument, it looks like `head = "custom-name"`. A bool argument looks like
-`surround = true`.
+`surround = true`. Bool attributes can also be written on their own, so `surround` is the same
+as `surround = true`. The same grammar is used for type, variant and field attributes; an unknown
+attribute is an error that lists the attributes accepted at that level:
+```rust,compile_fail
+# use sexpy::Sexpy;
+#[derive(Sexpy)]
+#[sexpy(hed = "plant")]       // error: `hed` is not a known attribute, expected one of: head, ...
+struct Plant(u64);
+```
 
 | Attribute    | Argument | Effect |
 |--------------|----------|--------|
-| `nohead`     | *none*   | Ignores head and only generates pattern from arguments |
+| `nohead`     | bool     | Ignores head and only generates pattern from arguments |
 | `head`       | string   | Use custom string as head instead of lowercase type name |
-| `surround`   | bool     | When true, match pattern surrounded with parens, brackets, or braces (true by default) |
-| `nosurround` | *none*   | Shortcut for `surround = false` |
+| `surround`   | bool     | When true, match pattern surrounded with parens or brackets (true by default) |
+| `nosurround` | bool     | Shortcut for `surround = false` |
+| `expect`     | string   | Describe the type in error messages, e.g. `expect = "a port definition"` |
+| `validate`   | string   | Path to a `fn(&Self) -> Result<(), String>` run on the parsed value |
+| `transparent`| bool     | For single field structs; parse exactly like the field, with no head or surround |
+| `alias`      | string   | Also accept this spelling of the head. Can be given more than once |
+| `deprecated` | bool     | Record a warning when an alias is used (see `Sexpy::parse_with_diagnostics`) |
+| `keyword`    | bool     | For enums without fields; parse each variant from a bare word (see below) |
+| `rename_all` | string   | Case the type head and variant heads with `"kebab-case"`, `"snake_case"`, `"camelCase"`, `"PascalCase"`, `"SCREAMING_SNAKE_CASE"`, `"SCREAMING-KEBAB-CASE"`, `"lowercase"` or `"UPPERCASE"`. Variants without a `head` are headed by their cased name |
+| `choice`     | string   | For enums; how variants are picked. `"ordered"` (default) takes the first variant that matches, `"longest"` tries them all and takes the one that consumes the most input, `"unique"` tries them all and reports an ambiguity error if more than one matches |
+| `case_insensitive` | bool | Match the heads and keywords given in this type regardless of ASCII case, like the `case_insensitive` option of [`ParseOptions`]. Types it contains keep their own setting |
+| `deny_unreachable` | bool | Report an error on variants of this enum that can never be parsed, see [Caveats](#caveats) |
 
 The following are variant level attributes. They look like:
 ```rust,ignore
@@ -116,22 +219,113 @@ enum Plant {
 | Attribute    | Argument | Effect |
 |--------------|----------|--------|
 | `head`       | string   | Use custom string as head instead of lowercase type name |
-| `surround`   | bool     | When true, match pattern surrounded with parens, brackets, or braces (false by default) |
+| `nohead`     | bool     | Do not match a head, even one given by the type's `rename_all` |
+| `surround`   | bool     | When true, match pattern surrounded with parens or brackets (false by default) |
+| `nosurround` | bool     | Shortcut for `surround = false` |
+| `expect`     | string   | Describe the variant in error messages instead of `Parsing <Variant>` |
+| `alias`      | string   | Also accept this spelling of the head. Can be given more than once |
+| `deprecated` | bool     | Record a warning when an alias is used (see `Sexpy::parse_with_diagnostics`) |
+
+Variant level attributes can also be put on fields. For example, `#[sexpy(expect = "a port width in bits")]`
+on a field makes a malformed width report `expected a port width in bits, found ...` instead of
+the generic number error.
+
+Fields also take `validate = "<path>"`, which names a function `fn(&T) -> Result<(), String>` that
+is called on the parsed field. An `Err` is reported like a syntax error, pointing at the field.
+```rust,ignore
+fn power_of_two(w: &u64) -> Result<(), String> { ... }
+
+#[derive(Sexpy)]
+struct Port {
+  name: String,
+  #[sexpy(validate = "power_of_two")]
+  width: u64,
+}
+```
+
+A field can be parsed as one type and stored as another:
+
+| Attribute    | Argument | Effect |
+|--------------|----------|--------|
+| `with`       | string   | Parse the field with `<module>::sexp_parse` instead of the field type's parser |
+| `from`       | string   | Parse the given `Sexpy` type and convert it with `From` |
+| `try_from`   | string   | Parse the given `Sexpy` type and convert it with `TryFrom`, reporting conversion errors at the field |
+
+```rust,ignore
+#[derive(Sexpy)]
+struct Port {
+  #[sexpy(from = "String")]
+  name: Sym,
+  #[sexpy(try_from = "u64")]
+  width: NonZeroU64,
+}
+```
+
+## Errors and Warnings
+`Sexpy::parse` renders errors as text. `Sexpy::parse_diagnostic` returns a structured
+[`diagnostic::Diagnostic`] instead, which can be rendered with a [`diagnostic::Renderer`]
+(with or without color, and with a file name) or, with the `json` feature, serialized to JSON.
+
+Parsers can also record warnings, which don't stop parsing, such as uses of deprecated
+aliases. `Sexpy::parse_with_diagnostics` returns them along with the parsed value.
+Hand written parsers record warnings with [`parsers::warn`].
+
+## Comments
+Wherever whitespace is allowed, the parsers also skip `;` line comments, `#| ... |#` block
+comments, which nest, and `#;` datum comments, which comment out the form or word that follows
+them. An unterminated block comment is an error.
+
+With the `keep_comments` option, skipped comments are not lost: a field marked
+`#[sexpy(comments)]` of type `Vec<Comment>` is not parsed but holds the comments written right
+before the form, and [`Commented<T>`] does the same for any `T`. `;;;` doc comments can be
+picked out with `Comment::doc`. See the [`comment`](mod@comment) module.
+
+## Parse Options
+`Sexpy::parse_with` takes a [`ParseOptions`] that changes the syntax for the whole parse,
+including every nested derived parser: the comment character, the delimiters that can surround
+a form, the nesting limit, whether heads are case sensitive, whether anything may follow the
+parsed value and whether comments are kept. `Sexpy::parse_diagnostic_with` and
+`Sexpy::parse_with_diagnostics_with` take options too, for structured errors and warnings. See
+the [`options`] module.
+
+## Streaming
+[`stream::StreamParser`] parses the top-level forms of a `BufRead` one at a time, as an iterator
+of `Result<T, stream::StreamError>`, reading in chunks and keeping only the current form in
+memory. Errors report their line and column in the whole stream.
+
+## Nesting
+Every pair of parentheses or brackets is one level of nesting, and input nested more than 128
+levels deep fails with a "nesting too deep" error instead of overflowing the stack. Use
+`Sexpy::parse_with_max_depth` or `ParseOptions::max_depth` to change the limit. Types marked
+`nosurround` add no level, so a type that contains itself without a surround, such as a
+`nosurround` list holding a `Box` of its own type, is only bounded by the length of the input.
+With the `stacker` feature enabled, the stack is grown on the heap whenever it runs low, so
+large limits are safe on any thread.
+Note that dropping a deeply nested value recurses too, and may need the same care.
 
 !*/
 
+pub mod comment;
+pub mod diagnostic;
 pub mod error;
+pub mod options;
 #[allow(unused)]
 pub mod parsers;
+mod session;
 pub mod std_impls;
+pub mod stream;
 
+pub use comment::{Comment, Commented};
 pub use nom;
+pub use options::ParseOptions;
 pub use sexpy_derive::Sexpy;
 
+use diagnostic::Diagnostic;
 use error::SexpyError;
 use nom::{
-    character::complete::{alpha1, char, digit1, none_of},
+    character::complete::{alpha1, char, digit1},
     combinator::opt,
+    error::{ErrorKind, ParseError},
     multi::many0,
     sequence::{preceded, tuple},
     Err, IResult,
@@ -148,12 +342,7 @@ pub trait Sexpy {
     where
         Self: Sized,
     {
-        match preceded(wordbreak0, Self::sexp_parse)(input) {
-            Ok((_, x)) => Ok(x),
-            Err(Err::Error(e)) => Err(e.convert_error(input)),
-            Err(Err::Failure(e)) => Err(e.convert_error(input)),
-            Err(Err::Incomplete(_)) => Err("Need more bytes to nom".to_string()),
-        }
+        parse_input(input).0.map_err(|e| e.convert_error(input))
     }
 
     /// Takes a string and tries calling the parser for this trait on it, converting
@@ -162,21 +351,145 @@ pub trait Sexpy {
     where
         Self: Sized,
     {
-        match preceded(wordbreak0, Self::sexp_parse)(input) {
-            Ok((_, x)) => Ok(x),
-            Err(Err::Error(e)) => Err(e.convert_error_verbose(input)),
-            Err(Err::Failure(e)) => Err(e.convert_error_verbose(input)),
-            Err(Err::Incomplete(_)) => {
-                Err("Incomplete input, need more bytes to nom".to_string())
-            }
+        parse_input(input)
+            .0
+            .map_err(|e| e.convert_error_verbose(input))
+    }
+
+    /// Takes a string and tries calling the parser for this trait on it, converting
+    /// any errors into a `Diagnostic` that can be rendered with a `diagnostic::Renderer`
+    #[allow(clippy::result_large_err)]
+    fn parse_diagnostic(input: &str) -> Result<Self, Diagnostic>
+    where
+        Self: Sized,
+    {
+        parse_input(input).0.map_err(|e| e.diagnostic(input))
+    }
+
+    /// Like `parse_diagnostic`, but also returns the warnings recorded while
+    /// parsing, such as uses of deprecated aliases
+    #[allow(clippy::result_large_err)]
+    fn parse_with_diagnostics(
+        input: &str,
+    ) -> Result<(Self, Vec<Diagnostic>), Diagnostic>
+    where
+        Self: Sized,
+    {
+        match parse_input(input) {
+            (Ok(x), warnings) => Ok((x, warnings)),
+            (Err(e), _) => Err(e.diagnostic(input)),
+        }
+    }
+
+    /// Like `parse`, but allows forms to be nested `max_depth` pairs of
+    /// delimiters deep instead of the default of 128. Deeper input fails with
+    /// a "nesting too deep" error. Each level takes up to a few KiB of stack,
+    /// so raise the limit only along with the thread's stack size or with the
+    /// `stacker` feature enabled.
+    fn parse_with_max_depth(
+        input: &str,
+        max_depth: usize,
+    ) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        Self::parse_with(input, &ParseOptions::new().max_depth(max_depth))
+    }
+
+    /// Like `parse`, but with the syntax described by `options`, such as a
+    /// different comment character or delimiters. See [`options`].
+    fn parse_with(input: &str, options: &ParseOptions) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        parse_input_with(input, options)
+            .0
+            .map_err(|e| e.convert_error(input))
+    }
+
+    /// Like `parse_diagnostic`, but with the syntax described by `options`
+    #[allow(clippy::result_large_err)]
+    fn parse_diagnostic_with(
+        input: &str,
+        options: &ParseOptions,
+    ) -> Result<Self, Diagnostic>
+    where
+        Self: Sized,
+    {
+        parse_input_with(input, options)
+            .0
+            .map_err(|e| e.diagnostic(input))
+    }
+
+    /// Like `parse_with_diagnostics`, but with the syntax described by
+    /// `options`
+    #[allow(clippy::result_large_err)]
+    fn parse_with_diagnostics_with(
+        input: &str,
+        options: &ParseOptions,
+    ) -> Result<(Self, Vec<Diagnostic>), Diagnostic>
+    where
+        Self: Sized,
+    {
+        match parse_input_with(input, options) {
+            (Ok(x), warnings) => Ok((x, warnings)),
+            (Err(e), _) => Err(e.diagnostic(input)),
         }
     }
 
     /// The parser for this trait. Should be automatically derivable from a type definition
-    /// in most cases
-    fn sexp_parse<'a>(
-        input: &'a str,
-    ) -> IResult<&'a str, Self, SexpyError<&'a str>>
+    /// in most cases.
+    ///
+    /// The options, warnings and nesting depth of a parse are kept on the
+    /// thread by the `parse*` functions, so calling this directly, or on
+    /// another thread, parses with the default options and drops warnings.
+    /// See [`options`].
+    fn sexp_parse(input: &str) -> IResult<&str, Self, SexpyError<&str>>
     where
         Self: Sized;
 }
+
+/// Runs the parser for `T` on `input`, skipping leading whitespace. When
+/// parsing fails and the delimiters in `input` are unbalanced, that is
+/// reported instead since it is almost always the real mistake.
+/// Also returns the warnings recorded while parsing.
+fn parse_input<T: Sexpy>(
+    input: &str,
+) -> (Result<T, SexpyError<&str>>, Vec<Diagnostic>) {
+    parse_input_with(input, &ParseOptions::default())
+}
+
+/// Like `parse_input`, with the syntax described by `options`
+fn parse_input_with<'a, T: Sexpy>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> (Result<T, SexpyError<&'a str>>, Vec<Diagnostic>) {
+    // delimiters are checked within the session, since they depend on
+    // the options
+    session::run(input, options, || {
+        match preceded(wordbreak0, T::sexp_parse)(input) {
+            Ok((rest, x)) if options.strict_eof => {
+                let rest = wordbreak0(rest).map_or(rest, |(r, _)| r);
+                if rest.is_empty() {
+                    Ok(x)
+                } else {
+                    let err = SexpyError {
+                        errors: vec![(
+                            rest,
+                            error::SexpyErrorKind::Expected("end of input"),
+                        )],
+                    };
+                    Err(check_delimiters(input).err().unwrap_or(err))
+                }
+            }
+            Ok((_, x)) => Ok(x),
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+                Err(check_delimiters(input).err().unwrap_or(e))
+            }
+            Err(Err::Incomplete(_)) => Err(SexpyError::from_error_kind(
+                &input[input.len()..],
+                ErrorKind::Complete,
+            )),
+        }
+    })
+}
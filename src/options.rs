@@ -0,0 +1,143 @@
+//! Options that change the syntax accepted by every parser during a parse.
+//!
+//! Options are given to [`Sexpy::parse_with`](crate::Sexpy::parse_with) and
+//! stay in effect for every `sexp_parse` call it makes on its thread,
+//! including the derived ones, so that one crate can parse several dialects:
+//!
+//! ```rust
+//! use sexpy::{ParseOptions, Sexpy};
+//!
+//! #[derive(Sexpy, Debug, PartialEq)]
+//! struct Port {
+//!     name: String,
+//!     width: u64,
+//! }
+//!
+//! let opts = ParseOptions::new()
+//!     .comment('#')
+//!     .delimiters(&[('(', ')'), ('{', '}')]);
+//! let port = Port::parse_with("{port foo # the width\n 32}", &opts);
+//! assert_eq!(port, Ok(Port { name: "foo".to_string(), width: 32 }));
+//! ```
+//!
+//! `sexp_parse` only takes the remaining input, so the options are kept in a
+//! thread local that the `Sexpy::parse*` functions set up for the length of
+//! the parse. This keeps the signature of `sexp_parse` and every hand written
+//! parser unchanged. It also means that calling `sexp_parse` directly, outside
+//! of those functions, or from a thread the parser spawns, uses the default
+//! options instead.
+
+/// The nesting depth allowed when none is given, counted in pairs of
+/// delimiters. A level parsed by one derived parser uses about 7 KiB of
+/// stack in debug builds and 1 KiB in release builds, so this fits
+/// comfortably in the 2 MiB stack of a spawned thread.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Characters that always end a word
+const BOUNDARY: &str = " \t\r\n()[]{}";
+
+/// Syntax options for a parse. The defaults match what `Sexpy::parse`
+/// accepts: `;` line comments, `#| |#` block comments and `#;` datum
+/// comments, parentheses and brackets, a nesting limit of 128, case
+/// sensitive heads, trailing input ignored and comments not kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseOptions {
+    pub(crate) comment: char,
+    pub(crate) block_comments: bool,
+    pub(crate) delimiters: Vec<(char, char)>,
+    pub(crate) max_depth: usize,
+    pub(crate) case_insensitive: bool,
+    pub(crate) strict_eof: bool,
+    pub(crate) keep_comments: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            comment: ';',
+            block_comments: true,
+            delimiters: vec![('(', ')'), ('[', ']')],
+            max_depth: DEFAULT_MAX_DEPTH,
+            case_insensitive: false,
+            strict_eof: false,
+            keep_comments: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// The default options
+    pub fn new() -> Self {
+        ParseOptions::default()
+    }
+
+    /// Start line comments with `c` instead of `;`
+    pub fn comment(mut self, c: char) -> Self {
+        self.comment = c;
+        self
+    }
+
+    /// Turn `#| ... |#` block comments and `#;` datum comments on or off,
+    /// for instance when `#` starts line comments
+    pub fn block_comments(mut self, yes: bool) -> Self {
+        self.block_comments = yes;
+        self
+    }
+
+    /// The pairs of delimiters that can surround a form, such as
+    /// `&[('(', ')'), ('{', '}')]`
+    pub fn delimiters(mut self, pairs: &[(char, char)]) -> Self {
+        self.delimiters = pairs.to_vec();
+        self
+    }
+
+    /// Allow forms to be nested `depth` levels deep. See
+    /// `Sexpy::parse_with_max_depth`.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// When true, heads and keywords match regardless of ASCII case.
+    /// Error messages still use the spelling given in the type definition.
+    /// Types can also ask for this with `#[sexpy(case_insensitive)]`.
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// When true, it is an error for anything but whitespace and comments
+    /// to follow the parsed value
+    pub fn strict_eof(mut self, yes: bool) -> Self {
+        self.strict_eof = yes;
+        self
+    }
+
+    /// When true, the comments skipped by the parsers are kept so that
+    /// `#[sexpy(comments)]` fields and `Commented` can attach them to values.
+    /// See the [`comment`](mod@crate::comment) module.
+    pub fn keep_comments(mut self, yes: bool) -> Self {
+        self.keep_comments = yes;
+        self
+    }
+
+    /// Whether `c` ends a word
+    pub(crate) fn is_boundary(&self, c: char) -> bool {
+        BOUNDARY.contains(c)
+            || c == self.comment
+            || self.delimiters.iter().any(|&(o, cl)| c == o || c == cl)
+    }
+
+    /// The closing delimiter for `open`, if it opens a form
+    pub(crate) fn closer(&self, open: char) -> Option<char> {
+        self.delimiters
+            .iter()
+            .find(|(o, _)| *o == open)
+            .map(|(_, close)| *close)
+    }
+
+    /// Whether `c` closes a form
+    pub(crate) fn is_closer(&self, c: char) -> bool {
+        self.delimiters.iter().any(|(_, close)| *close == c)
+    }
+}
@@ -1,15 +1,19 @@
-use crate::error::{context, SexpyError};
+use crate::comment::{Comment, CommentKind};
+use crate::diagnostic::Diagnostic;
+use crate::error::{context, SexpyError, SexpyErrorKind};
+use crate::session;
 use nom::{
     branch::alt,
-    bytes::complete::take_till,
     character::complete::{anychar, char, none_of, one_of},
-    combinator::{cut, map, peek},
-    error::ParseError,
-    multi::{many0, many1},
-    sequence::{delimited, preceded},
-    Err::Error,
+    combinator::{cut, map, peek, recognize, value},
+    error::{ErrorKind, ParseError},
+    multi::many0,
+    sequence::preceded,
+    Err::{Error, Failure},
     IResult,
 };
+use std::borrow::Cow;
+use std::ops::Range;
 
 /// The `inner` parser that ignores the result and returns unit instead
 pub fn ignore<'a, F, O1>(
@@ -21,29 +25,226 @@ where
     map(inner, |_| ())
 }
 
-/// Parses an s-expression comment; something that starts with `;` and ends with `\n`
-pub fn comment<'a>(
-    input: &'a str,
-) -> IResult<&'a str, (), SexpyError<&'a str>> {
-    ignore(preceded(char(';'), many0(none_of("\n"))))(input)
+/// Parses an s-expression comment; something that starts with `;` and ends with `\n`.
+/// The comment character can be changed with `ParseOptions::comment`.
+pub fn comment(input: &str) -> IResult<&str, (), SexpyError<&str>> {
+    let start = session::with_options(|o| o.comment);
+    ignore(preceded(char(start), many0(none_of("\n"))))(input)
+}
+
+/// Parses a `#| ... |#` block comment. Block comments nest, so a region
+/// that contains block comments can itself be commented out.
+pub fn block_comment(input: &str) -> IResult<&str, (), SexpyError<&str>> {
+    if !input.starts_with("#|") || !session::with_options(|o| o.block_comments)
+    {
+        return Err(Error(SexpyError::from_error_kind(input, ErrorKind::Tag)));
+    }
+    match block_comment_len(input) {
+        Some(len) => Ok((&input[len..], ())),
+        None => Err(Failure(SexpyError::unterminated_comment(input))),
+    }
+}
+
+/// Parses a `#;` datum comment, which comments out the s-expression
+/// following it: either a whole form or a single word
+pub fn datum_comment(input: &str) -> IResult<&str, (), SexpyError<&str>> {
+    if !input.starts_with("#;") || !session::with_options(|o| o.block_comments)
+    {
+        return Err(Error(SexpyError::from_error_kind(input, ErrorKind::Tag)));
+    }
+    let (rest, _) = wordbreak0(&input[2..])?;
+    match datum_len(rest) {
+        Ok(0) => Err(Failure(SexpyError {
+            errors: vec![(
+                rest,
+                SexpyErrorKind::Expected("an s-expression to comment out"),
+            )],
+        })),
+        Ok(len) => Ok((&rest[len..], ())),
+        Err(e) => Err(Failure(e)),
+    }
+}
+
+/// Matches a single whitespace character or a whole comment, returning
+/// the kind and text of the comment
+fn wordbreak_item(
+    input: &str,
+) -> IResult<&str, Option<(CommentKind, &str)>, SexpyError<&str>> {
+    alt((
+        value(None, one_of(" \t\r\n")),
+        map(recognize(block_comment), |s| Some((CommentKind::Block, s))),
+        map(recognize(datum_comment), |s| Some((CommentKind::Datum, s))),
+        map(recognize(comment), |s| Some((CommentKind::Line, s))),
+    ))(input)
+}
+
+/// Remembers the comments skipped between `input` and `rest`, so that they
+/// can be found with `leading_comments(rest)`
+fn record_comments(input: &str, rest: &str, items: Vec<(CommentKind, &str)>) {
+    let len = input.len() - rest.len();
+    if let Some(run) = session::span_of(input, len) {
+        session::record_comments(run, || {
+            items
+                .into_iter()
+                .filter_map(|(kind, text)| {
+                    Some(Comment {
+                        kind,
+                        text: text.to_string(),
+                        span: session::span_of(text, text.len())?,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// Matches at least `min` whitespace characters or comments. The comments
+/// are only collected with the `keep_comments` option.
+fn wordbreak(input: &str, min: usize) -> IResult<&str, (), SexpyError<&str>> {
+    let keep = session::with_options(|o| o.keep_comments);
+    let mut rest = input;
+    let mut count = 0;
+    let mut items = vec![];
+    loop {
+        match wordbreak_item(rest) {
+            Ok((next, item)) => {
+                if keep {
+                    items.extend(item)
+                }
+                rest = next;
+                count += 1;
+            }
+            Err(Error(e)) if count < min => {
+                return Err(Error(SexpyError::append(
+                    input,
+                    ErrorKind::Many1,
+                    e,
+                )))
+            }
+            Err(Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    if keep {
+        record_comments(input, rest, items);
+    }
+    Ok((rest, ()))
 }
 
 /// Matches a zero or more whitespace characters or comments
-pub fn wordbreak0<'a>(
-    input: &'a str,
-) -> IResult<&'a str, (), SexpyError<&'a str>> {
-    ignore(many0(alt((ignore(one_of(" \t\r\n")), comment))))(input)
+pub fn wordbreak0(input: &str) -> IResult<&str, (), SexpyError<&str>> {
+    wordbreak(input, 0)
 }
 
 /// Matches a one or more whitespace characters or comments
-pub fn wordbreak1<'a>(
+pub fn wordbreak1(input: &str) -> IResult<&str, (), SexpyError<&str>> {
+    wordbreak(input, 1)
+}
+
+/// The comments skipped right before `input`, that is, between the previous
+/// word or form and `input`. See the [`comment`](mod@crate::comment) module.
+pub fn leading_comments(input: &str) -> Vec<Comment> {
+    session::span_of(input, 0)
+        .map_or(vec![], |at| session::comments_before(at.start))
+}
+
+/// Runs `inner` on `input`, forgetting the comments before `input` once it
+/// succeeds since they now belong to the parsed value. Derived parsers with
+/// a `#[sexpy(comments)]` field wrap their body with this.
+pub fn attach_comments<'a, O, F>(
     input: &'a str,
-) -> IResult<&'a str, (), SexpyError<&'a str>> {
-    ignore(many1(alt((ignore(one_of(" \t\r\n")), comment))))(input)
+    inner: F,
+) -> IResult<&'a str, O, SexpyError<&'a str>>
+where
+    F: FnOnce(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>,
+{
+    let res = inner(input)?;
+    if let Some(at) = session::span_of(input, 0) {
+        session::forget_comments(at.start)
+    }
+    Ok(res)
+}
+
+/// The length of the block comment at the start of `input`, or `None`
+/// if it is never closed
+fn block_comment_len(input: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < input.len() {
+        let rest = &input[i..];
+        if rest.starts_with("#|") {
+            depth += 1;
+            i += 2;
+        } else if rest.starts_with("|#") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+/// The length of the s-expression at the start of `input`: a form up to its
+/// closing delimiter, or a single word. Zero if `input` starts with neither.
+/// Comments inside a form are skipped so that delimiters in them don't count.
+pub(crate) fn datum_len(input: &str) -> Result<usize, SexpyError<&str>> {
+    let options = session::with_options(|o| o.clone());
+    let first = match input.chars().next() {
+        Some(c) if options.closer(c).is_some() => c,
+        _ => return Ok(next_word(input).len()),
+    };
+
+    let mut stack = vec![(0, first)];
+    let mut i = first.len_utf8();
+    while let Some(c) = input[i..].chars().next() {
+        let rest = &input[i..];
+        if options.block_comments && rest.starts_with("#|") {
+            match block_comment_len(rest) {
+                Some(len) => i += len,
+                None => return Err(SexpyError::unterminated_comment(rest)),
+            }
+            continue;
+        }
+        // the form after `#;` is skipped along with everything else
+        if options.block_comments && rest.starts_with("#;") {
+            i += 2;
+            continue;
+        }
+        if c == options.comment {
+            i += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        }
+
+        let at = i;
+        i += c.len_utf8();
+        if options.closer(c).is_some() {
+            stack.push((at, c))
+        } else if options.is_closer(c) {
+            let (pos, open) = stack.pop().expect("stack is never empty");
+            let close = options.closer(open).unwrap_or(c);
+            if close != c {
+                let err = SexpyError::from_char(&input[at..], close);
+                return Err(SexpyError::unclosed(&input[pos..], open, err));
+            }
+            if stack.is_empty() {
+                return Ok(i);
+            }
+        }
+    }
+
+    let (pos, open) = stack.pop().expect("stack is never empty");
+    Err(SexpyError {
+        errors: vec![(&input[pos..], SexpyErrorKind::Unclosed(open))],
+    })
 }
 
 /// Create a parser that surrounds whatever `inner` parses
-/// with brackets or parentheses
+/// with brackets or parentheses, or the delimiters given by
+/// `ParseOptions::delimiters`
 pub fn surround<'a, O1, F>(
     inner: F,
     input: &'a str,
@@ -54,40 +255,406 @@ where
     // look the first char without consuming it
     let res: IResult<&'a str, char, SexpyError<&'a str>> = peek(anychar)(input);
 
-    if let Ok((_, '(')) = res {
-        // if its open paren, parse with parens
-        delimited(
-            char('('),
-            preceded(wordbreak0, cut(inner)),
-            context("closing paren", preceded(wordbreak0, char(')'))),
-        )(input)
-    } else if let Ok((_, '[')) = res {
-        // if its open bracket, parse with brackets
-        delimited(
-            char('['),
-            preceded(wordbreak0, cut(inner)),
-            context("closing bracket", preceded(wordbreak0, char(']'))),
-        )(input)
+    let delims = res.ok().and_then(|(_, open)| {
+        session::with_options(|o| Some((open, o.closer(open)?)))
+    });
+    let (open, close) = match delims {
+        Some(delims) => delims,
+        None => {
+            let first =
+                session::with_options(|o| o.delimiters.first().map(|d| d.0));
+            let first = first.unwrap_or('(');
+            return IResult::Err(Error(SexpyError::from_char(input, first)));
+        }
+    };
+    let ctx = match close {
+        ')' => "closing paren",
+        ']' => "closing bracket",
+        '}' => "closing brace",
+        _ => "closing delimiter",
+    };
+
+    /// Leaves the level entered below, even if `inner` panics
+    struct Leave;
+
+    impl Drop for Leave {
+        fn drop(&mut self) {
+            session::leave();
+        }
+    }
+
+    // every pair of delimiters is one level of nesting
+    if let Err(limit) = session::enter() {
+        return Err(Failure(SexpyError::too_deep(input, limit)));
+    }
+    let _leave = Leave;
+    let (next, res) =
+        preceded(char(open), preceded(wordbreak0, cut(inner)))(input)?;
+
+    // remember where the form was opened if it is never closed
+    match context(ctx, preceded(wordbreak0, char(close)))(next) {
+        Ok((next, _)) => Ok((next, res)),
+        Err(Error(e)) => Err(Error(SexpyError::unclosed(input, open, e))),
+        Err(Failure(e)) => Err(Failure(SexpyError::unclosed(input, open, e))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Runs `inner` and checks what it parsed with `check`. When `check` rejects
+/// the value, its message is reported as a failure spanning everything
+/// `inner` consumed, so that it is displayed like a syntax error.
+pub fn validate<'a, O, F, V>(
+    inner: F,
+    check: V,
+) -> impl Fn(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>,
+    V: Fn(&O) -> Result<(), String>,
+{
+    move |i: &'a str| {
+        let (next, res) = inner(i)?;
+        match check(&res) {
+            Ok(()) => Ok((next, res)),
+            Err(msg) => {
+                let len = i.len() - next.len();
+                Err(Failure(SexpyError::invalid(i, len, msg)))
+            }
+        }
+    }
+}
+
+/// Runs `inner` and converts what it parsed with `conv`, such as
+/// `TryFrom::try_from`. A failed conversion is reported as a failure
+/// spanning everything `inner` consumed.
+pub fn convert<'a, O1, O2, E, F, C>(
+    inner: F,
+    conv: C,
+) -> impl Fn(&'a str) -> IResult<&'a str, O2, SexpyError<&'a str>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, O1, SexpyError<&'a str>>,
+    C: Fn(O1) -> Result<O2, E>,
+    E: std::fmt::Display,
+{
+    move |i: &'a str| {
+        let (next, res) = inner(i)?;
+        match conv(res) {
+            Ok(res) => Ok((next, res)),
+            Err(e) => {
+                let len = i.len() - next.len();
+                Err(Failure(SexpyError::invalid(i, len, e.to_string())))
+            }
+        }
+    }
+}
+
+/// Records a warning about the `len` bytes at the start of `at`. Warnings
+/// don't stop parsing and are returned by `Sexpy::parse_with_diagnostics`.
+/// They are discarded if the parser that recorded them ends up backtracking,
+/// so a warning is only kept for the parse that actually succeeded.
+///
+/// ```rust
+/// use sexpy::{error::SexpyError, nom::IResult, parsers::warn, Sexpy};
+///
+/// struct Width(u64);
+///
+/// impl Sexpy for Width {
+///     fn sexp_parse(input: &str) -> IResult<&str, Self, SexpyError<&str>> {
+///         let (next, w) = u64::sexp_parse(input)?;
+///         if input.starts_with('0') && w != 0 {
+///             warn(input, input.len() - next.len(), "leading zeros");
+///         }
+///         Ok((next, Width(w)))
+///     }
+/// }
+///
+/// let (_, warnings) = Width::parse_with_diagnostics("008").ok().unwrap();
+/// assert_eq!(warnings[0].message, "leading zeros");
+/// assert_eq!(warnings[0].span, 0..3);
+/// ```
+pub fn warn<S: Into<String>>(at: &str, len: usize, message: S) {
+    if let Some(span) = session::span_of(at, len) {
+        session::report(Diagnostic::warning(span, message))
+    }
+}
+
+/// Records a warning built by `build`, which is given the byte range of the
+/// `len` bytes at the start of `at`. Use this to attach labels or notes; the
+/// spans of labels can be computed with `span_of`.
+pub fn warn_with<F>(at: &str, len: usize, build: F)
+where
+    F: FnOnce(Range<usize>) -> Diagnostic,
+{
+    if let Some(span) = session::span_of(at, len) {
+        session::report(build(span))
+    }
+}
+
+/// The byte range of the `len` bytes at the start of `at` in the input that is
+/// being parsed. Returns `None` outside of the `Sexpy::parse*` functions.
+pub fn span_of(at: &str, len: usize) -> Option<Range<usize>> {
+    session::span_of(at, len)
+}
+
+/// Runs `inner`, discarding the warnings it recorded if it fails. Wrap parsers
+/// with this wherever a failure makes the caller backtrack and try something
+/// else, like the alternatives of an enum.
+pub fn attempt<'a, O, F>(
+    inner: F,
+) -> impl Fn(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>,
+{
+    move |i: &'a str| {
+        let mark = session::mark();
+        let res = inner(i);
+        if res.is_err() {
+            session::rewind(mark);
+        }
+        res
+    }
+}
+
+/// Puts back the previous `ignore_case` setting of the session when dropped,
+/// even if a parser panics
+struct RestoreIgnoreCase(bool);
+
+impl Drop for RestoreIgnoreCase {
+    fn drop(&mut self) {
+        session::set_ignore_case(self.0);
+    }
+}
+
+/// Runs the body of a derived parser on `input`. The limit on nesting is
+/// kept by `surround`; with the `stacker` feature the stack is also grown on
+/// the heap here when it runs low, so that a large limit set with
+/// `Sexpy::parse_with_max_depth` is safe to use.
+pub fn nested<'a, O, F>(
+    input: &'a str,
+    inner: F,
+) -> IResult<&'a str, O, SexpyError<&'a str>>
+where
+    F: FnOnce(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>,
+{
+    // `#[sexpy(case_insensitive)]` only applies to the type it is on
+    let _restore = RestoreIgnoreCase(session::set_ignore_case(false));
+    grow_stack(|| inner(input))
+}
+
+/// Runs `inner` on `input` with heads and keywords matched regardless of
+/// ASCII case, as if the `case_insensitive` option was set. Derived parsers
+/// of types marked `#[sexpy(case_insensitive)]` wrap their body with this;
+/// the types they contain still decide for themselves.
+pub fn ignore_case<'a, O, F>(
+    input: &'a str,
+    inner: F,
+) -> IResult<&'a str, O, SexpyError<&'a str>>
+where
+    F: FnOnce(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>,
+{
+    let _restore = RestoreIgnoreCase(session::set_ignore_case(true));
+    inner(input)
+}
+
+#[cfg(feature = "stacker")]
+fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    // enough for a few levels of a derived parser in a debug build
+    const RED_ZONE: usize = 64 * 1024;
+    const NEW_STACK: usize = 1024 * 1024;
+    stacker::maybe_grow(RED_ZONE, NEW_STACK, f)
+}
+
+#[cfg(not(feature = "stacker"))]
+fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+/// How `choice` picks between its alternatives
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Choice {
+    /// The first alternative that succeeds wins, like `alt`
+    Ordered,
+    /// Every alternative is tried and the one that consumes the most input
+    /// wins. Ties go to the alternative listed first.
+    Longest,
+    /// Every alternative is tried and it is an error if more than one of
+    /// them succeeds
+    Unique,
+}
+
+/// A parser of `O`s that is one alternative of a `choice`
+pub type Alternative<'p, 'a, O> =
+    &'p dyn Fn(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>>;
+
+/// Tries the named parsers in `alts`, picking the result according to
+/// `mode`. Failing alternatives have their warnings discarded, as with
+/// `attempt`.
+///
+/// With `Choice::Ordered` a `Failure` stops the search like it does for
+/// `alt`. The other modes treat a `Failure` as that alternative not
+/// matching, so that one alternative committing to a head does not hide
+/// the others. `Choice::Unique` reports an `Ambiguous` failure naming every
+/// alternative that matched.
+///
+/// ```rust
+/// use sexpy::parsers::{choice, word, Choice};
+/// use sexpy::Sexpy;
+///
+/// let keyword = |i| word("let")(i).map(|(i, _)| (i, "let".to_string()));
+/// let alts = [("keyword", &keyword as _), ("name", &String::sexp_parse as _)];
+/// assert!(choice(Choice::Ordered, &alts)("let").is_ok());
+/// assert!(choice(Choice::Unique, &alts)("let").is_err());
+/// assert!(choice(Choice::Unique, &alts)("x").is_ok());
+/// ```
+pub fn choice<'p, 'a, O>(
+    mode: Choice,
+    alts: &'p [(&'static str, Alternative<'p, 'a, O>)],
+) -> impl Fn(&'a str) -> IResult<&'a str, O, SexpyError<&'a str>> + 'p {
+    move |i: &'a str| {
+        let mut err: Option<nom::Err<SexpyError<&'a str>>> = None;
+        // the longest match so far, with the warnings it recorded
+        let mut best: Option<(&'a str, O, Vec<Diagnostic>)> = None;
+        let mut matched = vec![];
+
+        for (name, alt) in alts {
+            let mark = session::mark();
+            match alt(i) {
+                Ok((rest, o)) if mode == Choice::Ordered => {
+                    return Ok((rest, o))
+                }
+                Ok((rest, o)) => {
+                    let warnings = session::split_off(mark);
+                    matched.push((*name, i.len() - rest.len()));
+                    let longer = match &best {
+                        Some((r, _, _)) => rest.len() < r.len(),
+                        None => true,
+                    };
+                    if longer {
+                        best = Some((rest, o, warnings));
+                    }
+                }
+                Err(Failure(e)) if mode == Choice::Ordered => {
+                    session::rewind(mark);
+                    return Err(Failure(e));
+                }
+                Err(nom::Err::Incomplete(n)) => {
+                    session::rewind(mark);
+                    return Err(nom::Err::Incomplete(n));
+                }
+                Err(e) => {
+                    session::rewind(mark);
+                    err = Some(match err {
+                        None => e,
+                        Some(prev) => furthest(prev, e),
+                    });
+                }
+            }
+        }
+
+        if mode == Choice::Unique && matched.len() > 1 {
+            let len = matched.iter().map(|m| m.1).max().unwrap_or(0);
+            let names = matched.into_iter().map(|m| m.0).collect();
+            return Err(Failure(SexpyError::ambiguous(i, len, names)));
+        }
+
+        match best {
+            Some((rest, o, warnings)) => {
+                session::extend(warnings);
+                Ok((rest, o))
+            }
+            None => Err(err.unwrap_or_else(|| {
+                Error(SexpyError::from_error_kind(i, ErrorKind::Alt))
+            })),
+        }
+    }
+}
+
+/// Combines the errors of two alternatives that did not match. A `Failure`
+/// wins over an `Error`, since the alternative committed to its input, and
+/// of two `Failure`s the one that got further is kept.
+fn furthest<'a>(
+    a: nom::Err<SexpyError<&'a str>>,
+    b: nom::Err<SexpyError<&'a str>>,
+) -> nom::Err<SexpyError<&'a str>> {
+    let rest =
+        |e: &SexpyError<&'a str>| e.errors.first().map_or(0, |e| e.0.len());
+    match (a, b) {
+        (Error(a), Error(b)) => Error(a.or(b)),
+        (Failure(a), Failure(b)) if rest(&b) < rest(&a) => Failure(b),
+        (Failure(a), _) | (_, Failure(a)) => Failure(a),
+        (a, _) => a,
+    }
+}
+
+/// The word at the start of `input`, up to the next word boundary. Does not
+/// consume anything; derived enums use it to pick a variant by its head.
+pub fn next_word(input: &str) -> &str {
+    let end = session::with_options(|o| input.find(|c| o.is_boundary(c)));
+    &input[..end.unwrap_or(input.len())]
+}
+
+/// The word at the start of `input` as derived enums compare it with their
+/// heads: lowercased when heads are case insensitive, so that `(LET ...)`
+/// picks the variant headed by `let` directly
+pub fn dispatch_word(input: &str) -> Cow<'_, str> {
+    let word = next_word(input);
+    if session::ignores_case() && word.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(word.to_ascii_lowercase())
     } else {
-        IResult::Err(Error(SexpyError::from_char(input, '(')))
+        Cow::Borrowed(word)
     }
 }
 
 /// Takes in a `word` and returns `()` if the first word matches, otherwise
-/// returns an Error
+/// returns an Error. Matches regardless of ASCII case when the
+/// `case_insensitive` option or type attribute is set.
 pub fn word<'a>(
     word: &'a str,
 ) -> impl Fn(&'a str) -> IResult<&'a str, (), SexpyError<&'a str>> {
     move |i: &'a str| {
-        let chars = " ()[]{}\n\t\r;";
-        let (rest, string) =
-            // take characters until word boundary
-            context("matching word", take_till(|c| chars.contains(c)))(i)?;
-        if string == word {
-            Ok((rest, ()))
+        let string = next_word(i);
+        let same = if session::ignores_case() {
+            string.eq_ignore_ascii_case(word)
         } else {
-            IResult::Err(Error(SexpyError::from_word(i, string.to_string())))
+            string == word
+        };
+        if same {
+            Ok((&i[string.len()..], ()))
+        } else {
+            IResult::Err(Error(SexpyError::from_keyword(
+                i,
+                string.to_string(),
+                word,
+            )))
+        }
+    }
+}
+
+/// Like `word`, but also accepts any of `aliases` in place of `word`. When
+/// `deprecated` is true, using an alias records a warning suggesting `word`.
+/// Errors only mention `word`, so that suggestions never point at an alias.
+pub fn word_or_alias<'a>(
+    word: &'a str,
+    aliases: &'a [&'a str],
+    deprecated: bool,
+) -> impl Fn(&'a str) -> IResult<&'a str, (), SexpyError<&'a str>> {
+    move |i: &'a str| {
+        let err = match self::word(word)(i) {
+            Err(Error(e)) => e,
+            res => return res,
+        };
+        for alias in aliases {
+            if let Ok(res) = self::word(alias)(i) {
+                if deprecated {
+                    warn(
+                        i,
+                        alias.len(),
+                        format!("`{}` is deprecated, use `{}`", alias, word),
+                    );
+                }
+                return Ok(res);
+            }
         }
+        Err(Error(err))
     }
 }
 
@@ -102,3 +669,142 @@ where
 {
     preceded(context("incorrect head", word(head_tag)), cut(inner))
 }
+
+/// Like `head`, but the head can also be spelled as any of `aliases`.
+/// See `word_or_alias`.
+pub fn head_or_alias<'a, O1, F>(
+    head_tag: &'a str,
+    aliases: &'a [&'a str],
+    deprecated: bool,
+    inner: F,
+) -> impl Fn(&'a str) -> IResult<&'a str, O1, SexpyError<&'a str>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, O1, SexpyError<&'a str>>,
+{
+    preceded(
+        context(
+            "incorrect head",
+            word_or_alias(head_tag, aliases, deprecated),
+        ),
+        cut(inner),
+    )
+}
+
+/// Checks that every `(`, `[` and `{` in `input` is closed by the matching
+/// delimiter, skipping over comments. This is run when parsing fails so that
+/// a missing or mismatched delimiter is reported where it happened rather
+/// than where the parser finally gave up, which can be far away.
+///
+/// When a form is never closed, indentation is used to guess which one is
+/// missing its closing delimiter: a line indented no further than the line a
+/// form was opened on probably should have come after that form was closed.
+pub fn check_delimiters(input: &str) -> Result<(), SexpyError<&str>> {
+    let options = session::with_options(|o| o.clone());
+    // open delimiters: (position, delimiter, indentation of its line)
+    let mut stack: Vec<(usize, char, usize)> = vec![];
+    // first (opener, line start, place in the stack) that looks like it
+    // should have been closed
+    let mut suspect: Option<((usize, char), usize, usize)> = None;
+    let mut indent = 0;
+    let mut line_start = true;
+    // whether a form was opened on the current line so far
+    let mut opened_on_line = false;
+    let mut i = 0;
+
+    while let Some(c) = input[i..].chars().next() {
+        if options.block_comments && input[i..].starts_with("#|") {
+            match block_comment_len(&input[i..]) {
+                // a comment spanning lines does not change the indentation
+                Some(len) => i += len,
+                None => {
+                    return Err(SexpyError::unterminated_comment(&input[i..]))
+                }
+            }
+            continue;
+        }
+        // the `;` of a datum comment does not start a line comment
+        if options.block_comments && input[i..].starts_with("#;") {
+            i += 2;
+            continue;
+        }
+        if c == options.comment {
+            // skip to the end of the line, keeping the newline
+            i += input[i..].find('\n').unwrap_or(input.len() - i);
+            continue;
+        }
+        let at = i;
+        i += c.len_utf8();
+        if c == '\n' {
+            indent = 0;
+            line_start = true;
+            opened_on_line = false;
+            continue;
+        }
+        if line_start && (c == ' ' || c == '\t') {
+            indent += 1;
+            continue;
+        }
+        if line_start {
+            line_start = false;
+            if let Some(&(pos, open, open_indent)) = stack.last() {
+                if suspect.is_none() && indent <= open_indent {
+                    suspect = Some(((pos, open), at, stack.len() - 1));
+                }
+            }
+        }
+
+        if options.closer(c).is_some() {
+            let line = input[..at].rfind('\n').map_or(0, |n| n + 1);
+            let open_indent = input[line..at]
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count();
+            stack.push((at, c, open_indent));
+            opened_on_line = true;
+        } else if options.is_closer(c) {
+            match stack.pop() {
+                Some((pos, open, _)) => {
+                    // the suspect was closed after all, unless the closer
+                    // follows a form opened on the same line, where it
+                    // probably belongs to an outer form instead
+                    let closes_suspect = match suspect {
+                        Some((_, _, idx)) => idx == stack.len(),
+                        None => false,
+                    };
+                    if closes_suspect && (stack.is_empty() || !opened_on_line) {
+                        suspect = None
+                    }
+                    let close = options.closer(open).unwrap_or(c);
+                    if close != c {
+                        let err = SexpyError::from_char(&input[at..], close);
+                        return Err(SexpyError::unclosed(
+                            &input[pos..],
+                            open,
+                            err,
+                        ));
+                    }
+                }
+                None => return Err(SexpyError::unmatched(&input[at..], c)),
+            }
+        }
+    }
+
+    match (stack.last(), suspect) {
+        (None, _) => Ok(()),
+        (Some(_), Some(((pos, open), line, _))) => {
+            let mut err = SexpyError {
+                errors: vec![(&input[pos..], SexpyErrorKind::Unclosed(open))],
+            };
+            err.errors.push((
+                &input[line..],
+                SexpyErrorKind::Label(
+                    "perhaps it should be closed before this",
+                ),
+            ));
+            Err(err)
+        }
+        (Some(&(pos, open, _)), None) => Err(SexpyError {
+            errors: vec![(&input[pos..], SexpyErrorKind::Unclosed(open))],
+        }),
+    }
+}
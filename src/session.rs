@@ -0,0 +1,204 @@
+//! State shared by every parser during one call to a `Sexpy::parse*` function.
+//!
+//! Parsers only receive the remaining input, so anything that has to be
+//! collected across the whole parse, such as warnings, lives in a thread local
+//! session that the entry points set up and tear down.
+
+use crate::comment::Comment;
+use crate::diagnostic::Diagnostic;
+use crate::options::ParseOptions;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub(crate) struct Session {
+    /// address and length of the input being parsed, used to turn the
+    /// remaining input at some point into an offset
+    source: Option<(usize, usize)>,
+    warnings: Vec<Diagnostic>,
+    /// how many pairs of delimiters are currently open
+    depth: usize,
+    /// whether the type being parsed has case insensitive heads, on top
+    /// of the `case_insensitive` option
+    ignore_case: bool,
+    options: ParseOptions,
+    /// the comments skipped by each run of whitespace that have not been
+    /// attached to a value yet, keyed by the offset where the run ends, along
+    /// with the offset where it starts. Only filled with `keep_comments`.
+    comments: HashMap<usize, (usize, Vec<Comment>)>,
+}
+
+thread_local! {
+    static SESSION: RefCell<Session> = RefCell::new(Session::default());
+}
+
+/// Restores the session that was active before `run` started, even if
+/// parsing panics
+struct Restore(Option<Session>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        if let Some(prev) = self.0.take() {
+            SESSION.with(|s| *s.borrow_mut() = prev);
+        }
+    }
+}
+
+/// Runs `f` in a fresh session for `input` with `options`, returning what it
+/// produced along with the warnings recorded while it ran. Sessions nest, so a
+/// parser may itself call one of the `Sexpy::parse*` functions; the nested
+/// session starts at the current depth since it runs on the same stack.
+pub(crate) fn run<T>(
+    input: &str,
+    options: &ParseOptions,
+    f: impl FnOnce() -> T,
+) -> (T, Vec<Diagnostic>) {
+    let depth = SESSION.with(|s| s.borrow().depth);
+    let session = Session {
+        source: Some((input.as_ptr() as usize, input.len())),
+        warnings: vec![],
+        depth,
+        ignore_case: false,
+        options: options.clone(),
+        comments: HashMap::new(),
+    };
+    let restore = Restore(Some(SESSION.with(|s| s.replace(session))));
+    let res = f();
+    let warnings =
+        SESSION.with(|s| std::mem::take(&mut s.borrow_mut().warnings));
+    drop(restore);
+    (res, warnings)
+}
+
+/// The byte range of the `len` bytes at the start of `at` in the input of
+/// the active session, if `at` is part of it
+pub(crate) fn span_of(at: &str, len: usize) -> Option<std::ops::Range<usize>> {
+    SESSION.with(|s| {
+        let (start, src_len) = s.borrow().source?;
+        let pos = at.as_ptr() as usize;
+        if pos >= start && pos <= start + src_len {
+            Some(pos - start..pos - start + len)
+        } else {
+            None
+        }
+    })
+}
+
+/// Records `diag` as a warning of the active session
+pub(crate) fn report(diag: Diagnostic) {
+    SESSION.with(|s| {
+        let mut s = s.borrow_mut();
+        if s.source.is_some() {
+            s.warnings.push(diag)
+        }
+    })
+}
+
+/// The number of warnings recorded so far, to be passed to `rewind`
+pub(crate) fn mark() -> usize {
+    SESSION.with(|s| s.borrow().warnings.len())
+}
+
+/// Forgets the warnings recorded since `mark` was taken
+pub(crate) fn rewind(mark: usize) {
+    SESSION.with(|s| s.borrow_mut().warnings.truncate(mark))
+}
+
+/// Removes and returns the warnings recorded since `mark` was taken,
+/// so that they can be put back with `extend` later
+pub(crate) fn split_off(mark: usize) -> Vec<Diagnostic> {
+    SESSION.with(|s| s.borrow_mut().warnings.split_off(mark))
+}
+
+/// Records `warnings` as warnings of the active session
+pub(crate) fn extend(warnings: Vec<Diagnostic>) {
+    SESSION.with(|s| s.borrow_mut().warnings.extend(warnings))
+}
+
+/// Enters one more level of nesting. Returns the depth limit as an error if
+/// that would exceed it, in which case the depth is left unchanged.
+pub(crate) fn enter() -> Result<(), usize> {
+    SESSION.with(|s| {
+        let mut s = s.borrow_mut();
+        if s.depth >= s.options.max_depth {
+            Err(s.options.max_depth)
+        } else {
+            s.depth += 1;
+            Ok(())
+        }
+    })
+}
+
+/// Leaves a level of nesting entered with `enter`
+pub(crate) fn leave() {
+    SESSION.with(|s| {
+        let mut s = s.borrow_mut();
+        s.depth = s.depth.saturating_sub(1)
+    })
+}
+
+/// Makes heads case insensitive or not for the type being parsed, returning
+/// the previous setting so that it can be restored afterwards
+pub(crate) fn set_ignore_case(yes: bool) -> bool {
+    SESSION.with(|s| std::mem::replace(&mut s.borrow_mut().ignore_case, yes))
+}
+
+/// Whether heads and keywords currently match regardless of ASCII case
+pub(crate) fn ignores_case() -> bool {
+    SESSION.with(|s| {
+        let s = s.borrow();
+        s.ignore_case || s.options.case_insensitive
+    })
+}
+
+/// Calls `f` with the options of the active session, or the default
+/// options outside of a session
+pub(crate) fn with_options<T>(f: impl FnOnce(&ParseOptions) -> T) -> T {
+    SESSION.with(|s| f(&s.borrow().options))
+}
+
+/// Records the comments skipped by a run of whitespace covering the byte
+/// range `run`. Parsers may skip the same run several times, or only the end
+/// of it, so `comments` is only called when the run is new or longer than
+/// the one already recorded.
+pub(crate) fn record_comments(
+    run: std::ops::Range<usize>,
+    comments: impl FnOnce() -> Vec<Comment>,
+) {
+    let new = SESSION.with(|s| {
+        let s = s.borrow();
+        let known = s.comments.get(&run.end).map(|(start, _)| *start);
+        s.source.is_some()
+            && match known {
+                Some(start) => run.start < start,
+                None => true,
+            }
+    });
+    // `comments` may look at the session itself, so it runs unborrowed
+    if new {
+        let comments = comments();
+        if !comments.is_empty() {
+            SESSION.with(|s| {
+                s.borrow_mut()
+                    .comments
+                    .insert(run.end, (run.start, comments))
+            });
+        }
+    }
+}
+
+/// The comments skipped by the run of whitespace ending at `offset`
+pub(crate) fn comments_before(offset: usize) -> Vec<Comment> {
+    SESSION.with(|s| {
+        s.borrow()
+            .comments
+            .get(&offset)
+            .map_or(vec![], |(_, comments)| comments.clone())
+    })
+}
+
+/// Forgets the comments skipped by the run of whitespace ending at `offset`
+/// once they have been attached to a value
+pub(crate) fn forget_comments(offset: usize) {
+    SESSION.with(|s| s.borrow_mut().comments.remove(&offset));
+}
@@ -8,9 +8,12 @@ impl Sexpy for String {
     where
         Self: Sized,
     {
-        let chars = " ()[]{}\\;\n\t\r";
-        let (next, (s, s1)) = tuple((alpha1, many0(none_of(chars))))(input)?;
-        Ok((next, format!("{}{}", s, s1.into_iter().collect::<String>())))
+        let (next, first) = alpha1(input)?;
+        let end = session::with_options(|o| {
+            next.find(|c| c == '\\' || o.is_boundary(c))
+        })
+        .unwrap_or(next.len());
+        Ok((&next[end..], format!("{}{}", first, &next[..end])))
     }
 }
 
@@ -88,8 +91,12 @@ impl<T: Sexpy> Sexpy for Option<T> {
     where
         Self: Sized,
     {
-        match opt(T::sexp_parse)(input) {
+        match opt(attempt(T::sexp_parse))(input) {
             Ok((next, res)) => Ok((next, res)),
+            // running out of depth is not a missing value
+            Err(nom::Err::Failure(e)) if e.is_too_deep() => {
+                Err(nom::Err::Failure(e))
+            }
             Err(_) => Ok((input, None)),
         }
     }
@@ -101,7 +108,8 @@ impl<T: Sexpy> Sexpy for Vec<T> {
     where
         Self: Sized,
     {
-        let (next, res) = many0(preceded(wordbreak0, T::sexp_parse))(input)?;
+        let (next, res) =
+            many0(attempt(preceded(wordbreak0, T::sexp_parse)))(input)?;
         Ok((next, res))
     }
 }
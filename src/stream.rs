@@ -0,0 +1,463 @@
+//! Parsing a sequence of top-level forms from a reader, one at a time.
+//!
+//! `Sexpy::parse` needs the whole input in memory. A [`StreamParser`] reads
+//! from a `BufRead` a chunk at a time and only keeps the form it is parsing,
+//! so it can go through inputs much larger than memory:
+//!
+//! ```rust
+//! use sexpy::{stream::StreamParser, Sexpy};
+//!
+//! #[derive(Sexpy, Debug, PartialEq)]
+//! struct Event {
+//!     time: u64,
+//!     name: String,
+//! }
+//!
+//! let trace = "(event 0 start)\n(event 5 stop) ; done\n";
+//! let events: Vec<Event> = StreamParser::new(trace.as_bytes())
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(events[1], Event { time: 5, name: "stop".to_string() });
+//! ```
+//!
+//! Errors point at the line and column they happened on in the whole stream.
+//! A form that fails to parse is reported and skipped; the stream stops after
+//! an I/O error or delimiters that don't match, since the end of the form is
+//! unknown then.
+
+use crate::diagnostic::{Diagnostic, Renderer};
+use crate::options::ParseOptions;
+use crate::{parse_input_with, Sexpy};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+/// The most bytes taken from the reader at once
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// An error from a [`StreamParser`]
+#[derive(Debug)]
+pub enum StreamError {
+    /// reading from the stream failed
+    Io(io::Error),
+    /// a form did not parse
+    Parse(FormError),
+}
+
+/// A form in a stream that did not parse. The spans of the diagnostic are
+/// byte offsets in the whole stream; `source` is the part of the stream
+/// around the form, starting at `offset`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormError {
+    pub diagnostic: Diagnostic,
+    pub source: String,
+    /// number of lines in the stream before `source`
+    pub line: usize,
+    /// number of characters before `source` on the line it starts on
+    pub column: usize,
+    /// byte offset of `source` in the stream
+    pub offset: usize,
+}
+
+impl FormError {
+    /// Renders the error with `renderer`, numbering lines and columns as in
+    /// the stream
+    pub fn render(&self, renderer: Renderer) -> String {
+        let diagnostic = shift(&self.diagnostic, |span| {
+            span.start - self.offset..span.end - self.offset
+        });
+        renderer
+            .line_offset(self.line)
+            .column_offset(self.column)
+            .render(&self.source, &diagnostic)
+    }
+}
+
+/// `diag` with every span moved by `f`
+fn shift(
+    diag: &Diagnostic,
+    f: impl Fn(&Range<usize>) -> Range<usize>,
+) -> Diagnostic {
+    let mut diag = diag.clone();
+    diag.span = f(&diag.span);
+    for label in &mut diag.labels {
+        label.span = f(&label.span);
+    }
+    diag
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(Renderer::plain()))
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "error: {}", e),
+            StreamError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Io(e) => Some(e),
+            StreamError::Parse(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+/// How much of the buffered input makes up the next form
+enum Scan {
+    /// the next form, with the whitespace and comments before it,
+    /// ends at this index of the buffer
+    Form(usize),
+    /// the form is cut off by the end of the buffer
+    Incomplete,
+    /// the delimiters of the form don't match, so where it ends is
+    /// unknown; the form is parsed up to the delimiter at this index
+    Broken(usize),
+    /// only whitespace and comments are left
+    Empty,
+}
+
+/// What the scanner is in the middle of
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// whitespace, or the inside of a form
+    Code,
+    /// a word at the top level
+    Word,
+    /// a line comment
+    Line,
+    /// block comments, this many deep
+    Block(usize),
+}
+
+/// Finds where top-level forms end, following the same rules as the
+/// parsers. The scan stops at the end of the buffer and picks up from
+/// there once more input is read, so every byte is only looked at once.
+struct Scanner {
+    /// index of the buffer the scan has reached
+    at: usize,
+    mode: Mode,
+    /// the delimiters of the forms that are open
+    stack: Vec<char>,
+    /// top-level `#;` comments still waiting for the datum they comment out
+    skip: usize,
+    /// index of the buffer where the datum of the next form starts,
+    /// once it has been reached
+    start: Option<usize>,
+}
+
+impl Scanner {
+    fn new() -> Self {
+        Scanner {
+            at: 0,
+            mode: Mode::Code,
+            stack: vec![],
+            skip: 0,
+            start: None,
+        }
+    }
+
+    /// Scans `buf` on from where the last scan stopped
+    fn scan(&mut self, buf: &str, options: &ParseOptions, eof: bool) -> Scan {
+        while let Some(c) = buf[self.at..].chars().next() {
+            let rest = &buf[self.at..];
+            // `#|`, `|#` and `#;` may be split across reads
+            if !eof && rest.len() == 1 && (c == '#' || c == '|') {
+                return Scan::Incomplete;
+            }
+            let block = options.block_comments;
+            let space = matches!(c, ' ' | '\t' | '\r' | '\n');
+            let mut len = c.len_utf8();
+            match self.mode {
+                Mode::Block(depth) if rest.starts_with("#|") => {
+                    self.mode = Mode::Block(depth + 1);
+                    len = 2;
+                }
+                Mode::Block(depth) if rest.starts_with("|#") => {
+                    self.mode = match depth {
+                        1 => Mode::Code,
+                        _ => Mode::Block(depth - 1),
+                    };
+                    len = 2;
+                }
+                Mode::Block(_) => (),
+                Mode::Line if c == '\n' => self.mode = Mode::Code,
+                Mode::Line => (),
+                Mode::Word if options.is_boundary(c) => {
+                    // the word ends before `c`, which is scanned again
+                    self.mode = Mode::Code;
+                    if let Some(end) = self.datum_end(self.at) {
+                        return Scan::Form(end);
+                    }
+                    continue;
+                }
+                Mode::Word => (),
+                Mode::Code if block && rest.starts_with("#|") => {
+                    self.mode = Mode::Block(1);
+                    len = 2;
+                }
+                Mode::Code if block && rest.starts_with("#;") => {
+                    // inside a form, the datum is skipped with the form
+                    if self.stack.is_empty() {
+                        self.skip += 1;
+                    }
+                    len = 2;
+                }
+                Mode::Code if c == options.comment => self.mode = Mode::Line,
+                Mode::Code if options.closer(c).is_some() => {
+                    if self.stack.is_empty() {
+                        self.begin();
+                    }
+                    self.stack.push(c)
+                }
+                Mode::Code if options.is_closer(c) => {
+                    let end = self.at + len;
+                    match self.stack.pop() {
+                        // a stray closing delimiter, left for the parser
+                        // to report
+                        None => {
+                            self.begin();
+                            self.at = end;
+                            self.skip = 0;
+                            return Scan::Form(end);
+                        }
+                        Some(open) if options.closer(open) != Some(c) => {
+                            return Scan::Broken(end)
+                        }
+                        Some(_) if self.stack.is_empty() => {
+                            if let Some(end) = self.datum_end(end) {
+                                return Scan::Form(end);
+                            }
+                            continue;
+                        }
+                        Some(_) => (),
+                    }
+                }
+                Mode::Code if space || !self.stack.is_empty() => (),
+                Mode::Code => {
+                    self.begin();
+                    self.mode = Mode::Word
+                }
+            }
+            self.at += len;
+        }
+
+        match self.mode {
+            Mode::Code | Mode::Line
+                if self.stack.is_empty() && self.skip == 0 =>
+            {
+                Scan::Empty
+            }
+            _ => Scan::Incomplete,
+        }
+    }
+
+    /// Notes that a top-level datum starts where the scan is
+    fn begin(&mut self) {
+        if self.skip == 0 {
+            self.start = Some(self.at);
+        }
+    }
+
+    /// Moves past a top-level datum that ends at `end`, returning where
+    /// the form ends unless the datum was commented out
+    fn datum_end(&mut self, end: usize) -> Option<usize> {
+        self.at = end;
+        if self.skip > 0 {
+            self.skip -= 1;
+            None
+        } else {
+            Some(end)
+        }
+    }
+}
+
+/// An iterator over the top-level forms of a `BufRead`, each parsed as a `T`.
+/// Like `Sexpy::parse_with` with `strict_eof`, every form has to be a `T`
+/// as a whole.
+pub struct StreamParser<R, T> {
+    reader: R,
+    options: ParseOptions,
+    /// input that has been read but not parsed
+    buf: String,
+    /// bytes at the end of the last read that are part of a character
+    /// the next read completes
+    partial: Vec<u8>,
+    /// where the input that has not been parsed starts in `buf`
+    pos: usize,
+    scanner: Scanner,
+    /// number of lines, characters on the last line and bytes of the
+    /// stream before `buf`
+    line: usize,
+    column: usize,
+    offset: usize,
+    eof: bool,
+    done: bool,
+    parses: PhantomData<fn() -> T>,
+}
+
+impl<R: BufRead, T: Sexpy> StreamParser<R, T> {
+    /// Parses the forms of `reader` with the default options
+    pub fn new(reader: R) -> Self {
+        StreamParser::with_options(reader, &ParseOptions::default())
+    }
+
+    /// Parses the forms of `reader` with the syntax described by `options`
+    pub fn with_options(reader: R, options: &ParseOptions) -> Self {
+        StreamParser {
+            reader,
+            options: options.clone().strict_eof(true),
+            buf: String::new(),
+            partial: vec![],
+            pos: 0,
+            scanner: Scanner::new(),
+            line: 0,
+            column: 0,
+            offset: 0,
+            eof: false,
+            done: false,
+            parses: PhantomData,
+        }
+    }
+
+    /// Drops the input that has been parsed and reads another chunk,
+    /// noting when the end of the stream is reached
+    fn fill(&mut self) -> io::Result<()> {
+        let (line, column, offset) = self.location_after(self.pos);
+        self.buf.replace_range(..self.pos, "");
+        self.line = line;
+        self.column = column;
+        self.offset = offset;
+        self.scanner.at -= self.pos;
+        if let Some(start) = &mut self.scanner.start {
+            *start -= self.pos;
+        }
+        self.pos = 0;
+
+        let chunk = loop {
+            match self.reader.fill_buf() {
+                Ok(chunk) => break chunk,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        };
+        let len = chunk.len().min(CHUNK_SIZE);
+        self.partial.extend_from_slice(&chunk[..len]);
+        self.reader.consume(len);
+        if len == 0 {
+            self.eof = true;
+        }
+
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(s) => s.len(),
+            // the rest of the character is in the next chunk
+            Err(e) if e.error_len().is_none() && !self.eof => e.valid_up_to(),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                ))
+            }
+        };
+        let text = std::str::from_utf8(&self.partial[..valid])
+            .expect("checked to be valid");
+        self.buf.push_str(text);
+        self.partial.drain(..valid);
+        Ok(())
+    }
+
+    /// The line, column and offset in the stream of index `at` of `buf`
+    fn location_after(&self, at: usize) -> (usize, usize, usize) {
+        let text = &self.buf[..at];
+        let offset = self.offset + at;
+        match text.rfind('\n') {
+            Some(n) => (
+                self.line + text.matches('\n').count(),
+                text[n + 1..].chars().count(),
+                offset,
+            ),
+            None => (self.line, self.column + text.chars().count(), offset),
+        }
+    }
+
+    /// Parses the next form, which ends at `end` of the buffer, and moves
+    /// past it
+    #[allow(clippy::result_large_err)]
+    fn parse(&mut self, end: usize) -> Result<T, StreamError> {
+        let form = &self.buf[self.pos..end];
+        let start = self.scanner.start.take().unwrap_or(self.pos);
+        let res = match parse_input_with::<T>(form, &self.options).0 {
+            Ok(x) => Ok(x),
+            Err(e) => {
+                // the error is shown with the form, or from where it
+                // happened if that is in the comments before the form
+                let diagnostic = e.diagnostic(form);
+                let spans = std::iter::once(&diagnostic.span)
+                    .chain(diagnostic.labels.iter().map(|l| &l.span));
+                let from = spans
+                    .map(|span| self.pos + span.start)
+                    .fold(start, usize::min);
+                let (line, column, offset) = self.location_after(from);
+                let moved = offset - (from - self.pos);
+                Err(StreamError::Parse(FormError {
+                    diagnostic: shift(&diagnostic, |span| {
+                        span.start + moved..span.end + moved
+                    }),
+                    source: self.buf[from..end].to_string(),
+                    line,
+                    column,
+                    offset,
+                }))
+            }
+        };
+        self.pos = end;
+        res
+    }
+
+    /// Parses everything left in the buffer as the last form
+    #[allow(clippy::result_large_err)]
+    fn parse_rest(&mut self) -> Result<T, StreamError> {
+        self.done = true;
+        self.parse(self.buf.len())
+    }
+}
+
+impl<R: BufRead, T: Sexpy> Iterator for StreamParser<R, T> {
+    type Item = Result<T, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.scanner.scan(&self.buf, &self.options, self.eof) {
+                Scan::Form(end) => return Some(self.parse(end)),
+                Scan::Empty if self.eof => self.done = true,
+                // the parser reports what is wrong with the form
+                Scan::Broken(end) => {
+                    self.done = true;
+                    return Some(self.parse(end));
+                }
+                Scan::Incomplete if self.eof => return Some(self.parse_rest()),
+                Scan::Empty | Scan::Incomplete => {
+                    if let Err(e) = self.fill() {
+                        self.done = true;
+                        return Some(Err(StreamError::Io(e)));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
@@ -283,3 +283,971 @@ fn option() {
         })
     )
 }
+
+#[test]
+fn misspelled_head() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    enum Plant {
+        #[sexpy(head = "cactus")]
+        Cactus(String, u64),
+        #[sexpy(head = "joshua-tree")]
+        JoshuaTree(String, u64),
+    }
+
+    let err = Plant::parse("(plant cactsu josh 400)").unwrap_err();
+    assert!(err.contains("unknown head `cactsu`, did you mean `cactus`?"));
+
+    let err = Plant::parse("(plant palm josh 400)").unwrap_err();
+    assert!(err.contains("expected one of `cactus`, `joshua-tree`"));
+
+    // a variant without a head in between keeps the heads around it
+    #[derive(Sexpy, Debug, PartialEq)]
+    enum Instr {
+        #[sexpy(head = "add")]
+        Add(u64, u64),
+        Lit(u64),
+        #[sexpy(head = "neg")]
+        Neg(u64),
+    }
+
+    let err = Instr::parse("(instr ad 1 2)").unwrap_err();
+    assert!(err.contains("unknown head `ad`, did you mean `add`?"));
+    let err = Instr::parse("(instr sub 1 2)").unwrap_err();
+    assert!(err.contains("expected one of `add`, `neg`, found `sub`"));
+}
+
+#[test]
+fn diagnostic_rendering() {
+    use sexpy::diagnostic::Renderer;
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Port {
+        name: String,
+        width: u64,
+    }
+
+    let input = "\n(port foo\n  10 20)";
+    let diag = Port::parse_diagnostic(input).unwrap_err();
+    assert_eq!(
+        Renderer::plain()
+            .filename("ports.sexp")
+            .render(input, &diag),
+        "error: expected ')', found '2'
+ --> ports.sexp:3:6
+  |
+2 | (port foo
+  | - form opened here
+3 |   10 20)
+  |      ^
+"
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_diagnostic() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Port {
+        name: String,
+        width: u64,
+    }
+
+    let input = "(port foo\n  10 20)";
+    let diag = Port::parse_diagnostic(input).unwrap_err();
+    let json = diag.to_json_value(input, Some("ports.sexp"));
+
+    assert_eq!(json["file"], "ports.sexp");
+    assert_eq!(json["severity"], "error");
+    assert_eq!(json["message"], "expected ')', found '2'");
+    assert_eq!(json["range"]["start"]["line"], 2);
+    assert_eq!(json["range"]["start"]["column"], 6);
+    assert_eq!(json["expected"][0], "')'");
+    assert_eq!(json["context"][0], "closing paren");
+    assert_eq!(json["labels"][0]["message"], "form opened here");
+    assert_eq!(json["labels"][0]["range"]["start"]["offset"], 0);
+}
+
+#[test]
+fn unbalanced_delimiters() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Port {
+        name: String,
+        width: u64,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Comp {
+        name: String,
+        ports: Vec<Port>,
+    }
+
+    let err = Comp::parse("(comp foo\n  (port a 1\n  (port b 2))").unwrap_err();
+    assert!(err.starts_with("error: unclosed '('\n --> <input>:2:3\n"));
+    assert!(err.contains("perhaps it should be closed before this"));
+
+    let err = Comp::parse("(comp foo (port a 1]))").unwrap_err();
+    assert!(err.starts_with("error: expected ')', found ']'\n"));
+    assert!(err.contains("form opened here"));
+
+    let err = Comp::parse("(comp foo\n ; (port\n port a 1))").unwrap_err();
+    assert!(err.contains("unexpected ')' with no matching opening delimiter"));
+    // a form closed on a line of its own is not blamed for a later one
+    let input = "(a\n)\n(b\n  (c 1\n  (d 2))";
+    let err = sexpy::parsers::check_delimiters(input).unwrap_err();
+    assert_eq!(
+        err.errors[0],
+        (&input[10..], sexpy::error::SexpyErrorKind::Unclosed('('))
+    );
+    assert_eq!(
+        err.errors[1],
+        (
+            &input[17..],
+            sexpy::error::SexpyErrorKind::Label(
+                "perhaps it should be closed before this"
+            )
+        )
+    );
+}
+
+#[test]
+fn custom_expectations() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(expect = "a port definition")]
+    struct Port {
+        name: String,
+        #[sexpy(expect = "a port width in bits")]
+        width: u64,
+    }
+
+    let err = Port::parse("(port foo bar)").unwrap_err();
+    assert!(err.starts_with(
+        "error: expected a port width in bits, found `bar`\n --> <input>:1:11\n"
+    ));
+
+    let err = Port::parse_verbose("portdef").unwrap_err();
+    assert!(
+        err.starts_with("error: expected a port definition, found `portdef`")
+    );
+
+    let diag = Port::parse_diagnostic("(port foo (10))").unwrap_err();
+    assert_eq!(diag.expected, vec!["a port width in bits".to_string()]);
+    assert_eq!(diag.context, vec!["a port definition".to_string()]);
+}
+
+#[test]
+fn validation() {
+    fn power_of_two(w: &u64) -> Result<(), String> {
+        if w.is_power_of_two() {
+            Ok(())
+        } else {
+            Err(format!("width must be a power of two, got {}", w))
+        }
+    }
+
+    fn not_reserved(p: &Port) -> Result<(), String> {
+        if p.name == "clk" {
+            Err("`clk` is a reserved port name".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(validate = "not_reserved")]
+    struct Port {
+        name: String,
+        #[sexpy(validate = "power_of_two")]
+        width: u64,
+    }
+
+    assert_eq!(
+        Port::parse("(port foo 16)"),
+        Ok(Port {
+            name: "foo".to_string(),
+            width: 16
+        })
+    );
+
+    let err = Port::parse("(port foo\n  12)").unwrap_err();
+    assert!(err.starts_with(
+        "error: width must be a power of two, got 12\n --> <input>:2:3\n"
+    ));
+    assert!(err.contains("2 |   12)\n  |   ^^\n"));
+
+    let err = Port::parse("(port clk 1)").unwrap_err();
+    assert!(err.starts_with("error: `clk` is a reserved port name\n"));
+    assert!(err.contains("^^^^^^^^^^^^"));
+}
+
+#[test]
+fn conversions() {
+    use std::num::NonZeroU64;
+
+    #[derive(Debug, PartialEq)]
+    struct Sym(String);
+
+    impl From<String> for Sym {
+        fn from(s: String) -> Self {
+            Sym(s.to_uppercase())
+        }
+    }
+
+    mod hex {
+        use sexpy::{error::SexpyError, nom::IResult};
+
+        pub fn sexp_parse(input: &str) -> IResult<&str, u64, SexpyError<&str>> {
+            let (next, digits) =
+                sexpy::nom::character::complete::hex_digit1(input)?;
+            Ok((next, u64::from_str_radix(digits, 16).unwrap()))
+        }
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Port {
+        #[sexpy(from = "String")]
+        name: Sym,
+        #[sexpy(try_from = "u64")]
+        width: NonZeroU64,
+        #[sexpy(with = "hex")]
+        addr: u64,
+    }
+
+    assert_eq!(
+        Port::parse("(port foo 8 ff)"),
+        Ok(Port {
+            name: Sym("FOO".to_string()),
+            width: NonZeroU64::new(8).unwrap(),
+            addr: 255,
+        })
+    );
+
+    let err = Port::parse("(port foo 0 ff)").unwrap_err();
+    assert!(err.starts_with(
+        "error: out of range integral type conversion attempted\n --> <input>:1:11\n"
+    ));
+}
+
+#[test]
+fn transparent() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(transparent)]
+    struct Width(u64);
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(transparent)]
+    struct Name {
+        name: String,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Port {
+        name: Name,
+        width: Width,
+    }
+
+    assert_eq!(Width::parse("32"), Ok(Width(32)));
+    assert_eq!(
+        Port::parse("(port foo 32)"),
+        Ok(Port {
+            name: Name {
+                name: "foo".to_string()
+            },
+            width: Width(32)
+        })
+    );
+}
+
+#[test]
+fn keyword_enum() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(keyword, deny_unreachable)]
+    enum Op {
+        Add,
+        Sub,
+        #[sexpy(head = "shift-left")]
+        Shl,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Instr {
+        op: Op,
+        args: Vec<u64>,
+    }
+
+    assert_eq!(Op::parse("sub"), Ok(Op::Sub));
+    assert_eq!(
+        Instr::parse("(instr shift-left 1 2)"),
+        Ok(Instr {
+            op: Op::Shl,
+            args: vec![1, 2]
+        })
+    );
+
+    let err = Op::parse("ad").unwrap_err();
+    assert!(err.contains("unknown head `ad`, did you mean `add`?"));
+    let err = Op::parse("mul").unwrap_err();
+    assert!(err.contains("expected one of `add`, `sub`, `shift-left`"));
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(keyword)]
+    enum Arith {
+        #[sexpy(alias = "plus")]
+        Add,
+        #[sexpy(alias = "minus", deprecated)]
+        Sub,
+    }
+
+    assert_eq!(Arith::parse("plus"), Ok(Arith::Add));
+    let (op, warnings) = Arith::parse_with_diagnostics("minus").unwrap();
+    assert_eq!(op, Arith::Sub);
+    assert_eq!(warnings[0].message, "`minus` is deprecated, use `sub`");
+}
+
+#[test]
+fn rename_all() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(rename_all = "kebab-case")]
+    enum PlantKind {
+        PalmTree(String),
+        #[sexpy(head = "cactus")]
+        BarrelCactus(u64),
+        JoshuaTree,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(keyword, rename_all = "SCREAMING_SNAKE_CASE")]
+    enum Op {
+        ShiftLeft,
+        HTTPGet,
+    }
+
+    assert_eq!(
+        PlantKind::parse("(plant-kind palm-tree foo)"),
+        Ok(PlantKind::PalmTree("foo".to_string()))
+    );
+    assert_eq!(
+        PlantKind::parse("(plant-kind cactus 4)"),
+        Ok(PlantKind::BarrelCactus(4))
+    );
+    assert_eq!(
+        PlantKind::parse("(plant-kind joshua-tree)"),
+        Ok(PlantKind::JoshuaTree)
+    );
+    assert_eq!(Op::parse("SHIFT_LEFT"), Ok(Op::ShiftLeft));
+    assert_eq!(Op::parse("HTTP_GET"), Ok(Op::HTTPGet));
+}
+
+#[test]
+fn head_aliases() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead)]
+    enum Def {
+        #[sexpy(head = "defn", alias = "fun", alias = "func", deprecated)]
+        Func(String, u64),
+        #[sexpy(head = "defvar", alias = "var")]
+        Var(String),
+    }
+
+    let (def, warnings) = Def::parse_with_diagnostics("(defn foo 2)").unwrap();
+    assert_eq!(def, Def::Func("foo".to_string(), 2));
+    assert!(warnings.is_empty());
+
+    let (def, warnings) = Def::parse_with_diagnostics("(func foo 2)").unwrap();
+    assert_eq!(def, Def::Func("foo".to_string(), 2));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].message, "`func` is deprecated, use `defn`");
+    assert_eq!(warnings[0].span, 1..5);
+
+    let (def, warnings) = Def::parse_with_diagnostics("(var x)").unwrap();
+    assert_eq!(def, Def::Var("x".to_string()));
+    assert!(warnings.is_empty());
+
+    let err = Def::parse("(defun foo 2)").unwrap_err();
+    assert!(err.contains("unknown head `defun`, did you mean `defn`?"));
+}
+
+#[test]
+fn warnings() {
+    use sexpy::{error::SexpyError, nom::IResult, parsers::warn};
+
+    #[derive(Debug, PartialEq)]
+    struct Width(u64);
+
+    impl Sexpy for Width {
+        fn sexp_parse(input: &str) -> IResult<&str, Self, SexpyError<&str>> {
+            let (next, w) = u64::sexp_parse(input)?;
+            if w > 64 {
+                warn(input, input.len() - next.len(), "very wide port");
+            }
+            Ok((next, Width(w)))
+        }
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    enum Port {
+        // tried first, but fails after its width warned
+        Named(Width, String),
+        Anon(Width),
+    }
+
+    let input = "(port 128)";
+    let (port, warnings) = Port::parse_with_diagnostics(input).unwrap();
+    assert_eq!(port, Port::Anon(Width(128)));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        sexpy::diagnostic::Renderer::plain().render(input, &warnings[0]),
+        "warning: very wide port
+ --> <input>:1:7
+  |
+1 | (port 128)
+  |       ^^^
+"
+    );
+
+    let (_, warnings) = Port::parse_with_diagnostics("(port 8)").unwrap();
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn bool_attributes() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(surround = false)]
+    struct Range {
+        lo: u64,
+        hi: u64,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead = true, surround = false, rename_all = "kebab-case")]
+    enum Bound {
+        #[sexpy(surround = true)]
+        Between(Range),
+        #[sexpy(nohead)]
+        Exactly(u64),
+        #[sexpy(nosurround)]
+        AtMost(u64),
+    }
+
+    assert_eq!(Range::parse("range 1 2"), Ok(Range { lo: 1, hi: 2 }));
+    assert_eq!(
+        Bound::parse("(between range 1 2)"),
+        Ok(Bound::Between(Range { lo: 1, hi: 2 }))
+    );
+    assert_eq!(Bound::parse("at-most 3"), Ok(Bound::AtMost(3)));
+    assert_eq!(Bound::parse("4"), Ok(Bound::Exactly(4)));
+}
+
+#[test]
+fn choice_modes() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(choice = "longest")]
+    enum Range {
+        Point(u64),
+        Span(u64, u64),
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(keyword)]
+    enum Bool {
+        True,
+        False,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(choice = "unique", nohead)]
+    enum Atom {
+        Flag(Bool),
+        Symbol(String),
+    }
+
+    assert_eq!(Range::parse("(range 1)"), Ok(Range::Point(1)));
+    assert_eq!(Range::parse("(range 1 2)"), Ok(Range::Span(1, 2)));
+    assert_eq!(Atom::parse("(x)"), Ok(Atom::Symbol("x".to_string())));
+    assert_eq!(
+        Atom::parse("(true)"),
+        Err(
+            "error: ambiguous input, it can be parsed as `Flag` or `Symbol`
+ --> <input>:1:2
+  |
+1 | (true)
+  |  ^^^^
+"
+            .to_string()
+        )
+    );
+}
+
+#[test]
+fn head_dispatch() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead)]
+    enum Instr {
+        Lit(u64),
+        #[sexpy(head = "add")]
+        Add(u64, u64),
+        #[sexpy(head = "neg", alias = "negate")]
+        Neg(u64),
+        #[sexpy(head = "nop")]
+        Nop,
+    }
+
+    assert_eq!(Instr::parse("(add 1 2)"), Ok(Instr::Add(1, 2)));
+    assert_eq!(Instr::parse("(negate 3)"), Ok(Instr::Neg(3)));
+    assert_eq!(Instr::parse("(4)"), Ok(Instr::Lit(4)));
+    assert_eq!(Instr::parse("(nop)"), Ok(Instr::Nop));
+    assert_eq!(
+        Instr::parse("(ad 1 2)"),
+        Err("error: unknown head `ad`, did you mean `add`?
+ --> <input>:1:2
+  |
+1 | (ad 1 2)
+  |  ^^
+"
+        .to_string())
+    );
+
+    // with a type head, the variant head comes after a word break
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(deny_unreachable)]
+    enum Op {
+        #[sexpy(head = "add")]
+        Add(u64, u64),
+        #[sexpy(head = "neg")]
+        Neg(u64),
+    }
+
+    assert_eq!(Op::parse("(op add 1 2)"), Ok(Op::Add(1, 2)));
+    assert_eq!(Op::parse("(op ; negate\n  neg 3)"), Ok(Op::Neg(3)));
+    assert!(Op::parse("(op sub 1 2)")
+        .unwrap_err()
+        .contains("expected one of `add`, `neg`, found `sub`"));
+}
+
+#[test]
+fn many_variants() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(keyword)]
+    enum Reg {
+        R0,
+        R1,
+        R2,
+        R3,
+        R4,
+        R5,
+        R6,
+        R7,
+        R8,
+        R9,
+        R10,
+        R11,
+        R12,
+        R13,
+        R14,
+        R15,
+        R16,
+        R17,
+        R18,
+        R19,
+        R20,
+        R21,
+        R22,
+        R23,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead, rename_all = "kebab-case")]
+    enum Instr {
+        Add(Reg, Reg),
+        Sub(Reg, Reg),
+        Mul(Reg, Reg),
+        Div(Reg, Reg),
+        Rem(Reg, Reg),
+        And(Reg, Reg),
+        Or(Reg, Reg),
+        Xor(Reg, Reg),
+        Shl(Reg, Reg),
+        Shr(Reg, Reg),
+        Sar(Reg, Reg),
+        Rol(Reg, Reg),
+        Ror(Reg, Reg),
+        Not(Reg),
+        Neg(Reg),
+        Inc(Reg),
+        Dec(Reg),
+        Load(Reg, u64),
+        Store(Reg, u64),
+        Push(Reg),
+        Pop(Reg),
+        Jump(u64),
+        Nop,
+    }
+
+    assert_eq!(Reg::parse("r23"), Ok(Reg::R23));
+    assert_eq!(Instr::parse("(pop r0)"), Ok(Instr::Pop(Reg::R0)));
+    assert_eq!(Instr::parse("(nop)"), Ok(Instr::Nop));
+}
+
+#[test]
+fn many_fields() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Config {
+        a: u64,
+        b: u64,
+        c: u64,
+        d: u64,
+        e: u64,
+        f: u64,
+        g: u64,
+        h: u64,
+        i: u64,
+        j: u64,
+        k: u64,
+        l: u64,
+        m: u64,
+        n: u64,
+        o: u64,
+        p: u64,
+        q: u64,
+        r: u64,
+        s: u64,
+        t: u64,
+        u: u64,
+        v: u64,
+        rest: String,
+    }
+
+    let input =
+        "(config 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 end)";
+    let config = Config::parse(input).unwrap();
+    assert_eq!((config.a, config.i, config.v), (1, 9, 22));
+    assert_eq!(config.rest, "end");
+
+    let err =
+        Config::parse_diagnostic("(config 1 2 3 4 5 6 7 8 9 x)").unwrap_err();
+    assert_eq!(err.context, vec!["j"]);
+}
+
+#[test]
+fn tuple_and_unit_structs() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Pair(String, u64);
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(head = "nil")]
+    struct Nil;
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(head = "cons")]
+    struct Cons(Pair, #[sexpy(expect = "an empty list")] Nil);
+
+    assert_eq!(Pair::parse("(pair a 1)"), Ok(Pair("a".to_string(), 1)));
+    assert_eq!(Nil::parse("(nil)"), Ok(Nil));
+    assert_eq!(
+        Cons::parse("(cons (pair a 1) (nil))"),
+        Ok(Cons(Pair("a".to_string(), 1), Nil))
+    );
+
+    let err = Pair::parse_diagnostic("(pair a b)").unwrap_err();
+    assert_eq!(err.context, vec!["field 1"]);
+}
+
+#[test]
+fn depth_limit() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead)]
+    enum Expr {
+        #[sexpy(head = "neg")]
+        Neg(Box<Expr>),
+        Num(u64),
+    }
+
+    let nested = |n| format!("{}(1){}", "(neg ".repeat(n), ")".repeat(n));
+
+    assert!(Expr::parse(&nested(100)).is_ok());
+    assert!(Expr::parse(&nested(10_000))
+        .unwrap_err()
+        .starts_with("error: nesting too deep, the limit is 128 levels"));
+    assert_eq!(
+        Expr::parse_with_max_depth(&nested(2), 2),
+        Err("error: nesting too deep, the limit is 2 levels
+ --> <input>:1:11
+  |
+1 | (neg (neg (1)))
+  |           ^
+"
+        .to_string())
+    );
+
+    // only delimiters count, not the derived parsers in between
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead, nosurround)]
+    struct Wrapped(Expr);
+
+    assert!(Wrapped::parse_with_max_depth(&nested(2), 3).is_ok());
+
+    // an optional child that is too deep is not a missing child
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Node {
+        child: Option<Box<Node>>,
+    }
+
+    let nodes = |n| format!("{}{}", "(node ".repeat(n), ")".repeat(n));
+    assert!(Node::parse(&nodes(100)).is_ok());
+    assert!(Node::parse(&nodes(200))
+        .unwrap_err()
+        .starts_with("error: nesting too deep, the limit is 128 levels"));
+}
+
+#[test]
+fn parse_options() {
+    use sexpy::ParseOptions;
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead)]
+    enum Stmt {
+        #[sexpy(head = "let")]
+        Let(String, u64),
+        #[sexpy(head = "print", alias = "echo", deprecated)]
+        Print(String),
+    }
+
+    let lisp = ParseOptions::new().strict_eof(true);
+    let shouty = ParseOptions::new()
+        .case_insensitive(true)
+        .comment('#')
+        .delimiters(&[('{', '}')]);
+
+    assert_eq!(
+        Stmt::parse("(let x 1) (print x)"),
+        Ok(Stmt::Let("x".to_string(), 1))
+    );
+    assert_eq!(
+        Stmt::parse_with("(let x 1) (print x)", &lisp),
+        Err("error: expected end of input, found '('
+ --> <input>:1:11
+  |
+1 | (let x 1) (print x)
+  |           ^
+"
+        .to_string())
+    );
+    assert_eq!(
+        Stmt::parse_with("{LET x # one\n 1}", &shouty),
+        Ok(Stmt::Let("x".to_string(), 1))
+    );
+    assert!(Stmt::parse_with("(let x 1)", &shouty).is_err());
+
+    // structured errors and warnings work with options too
+    let diag = Stmt::parse_diagnostic_with("{let x y}", &shouty).unwrap_err();
+    assert_eq!(diag.span, 7..8);
+    let (stmt, warnings) =
+        Stmt::parse_with_diagnostics_with("{ECHO x}", &shouty).unwrap();
+    assert_eq!(stmt, Stmt::Print("x".to_string()));
+    assert_eq!(warnings[0].message, "`echo` is deprecated, use `print`");
+}
+
+#[test]
+fn block_comments() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Pair {
+        left: u64,
+        right: u64,
+    }
+
+    assert_eq!(
+        Pair::parse("(pair #| outer #| inner |# (( |# 1 #;(2 [3]) 4)"),
+        Ok(Pair { left: 1, right: 4 })
+    );
+    assert_eq!(
+        Pair::parse("(pair 1 #; 2 3)"),
+        Ok(Pair { left: 1, right: 3 })
+    );
+    assert_eq!(
+        Pair::parse("(pair #;(1) 2 x)"),
+        Err("error: in Digit, found 'x'
+ --> <input>:1:15
+  |
+1 | (pair #;(1) 2 x)
+  |               ^
+"
+        .to_string())
+    );
+    assert_eq!(
+        Pair::parse("(pair 1 #| 2)"),
+        Err("error: unterminated block comment
+ --> <input>:1:9
+  |
+1 | (pair 1 #| 2)
+  |         ^^
+"
+        .to_string())
+    );
+}
+
+#[test]
+fn preserve_comments() {
+    use sexpy::comment::CommentKind;
+    use sexpy::{Comment, Commented, ParseOptions};
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead)]
+    enum Stmt {
+        #[sexpy(head = "let")]
+        Let(#[sexpy(comments)] Vec<Comment>, String, u64),
+        #[sexpy(head = "print")]
+        Print(String),
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(head = "block")]
+    struct Block {
+        stmts: Vec<Commented<Stmt>>,
+    }
+
+    let input = ";;; the block
+(block
+  ;;; first
+  ;;; binding
+  #| set up |# (let x 1)
+  #;(print y) (print x))";
+    let keep = ParseOptions::new().keep_comments(true);
+    let block = Block::parse_with(input, &keep).unwrap();
+    let kinds: Vec<CommentKind> =
+        block.stmts[0].comments.iter().map(|c| c.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![CommentKind::Line, CommentKind::Line, CommentKind::Block]
+    );
+    assert_eq!(block.stmts[0].doc(), Some("first\nbinding".to_string()));
+    assert_eq!(
+        block.stmts[1].comments[0].to_string(),
+        "#;(print y)".to_string()
+    );
+    assert_eq!(
+        &input[block.stmts[1].comments[0].span.clone()],
+        "#;(print y)"
+    );
+    match &block.stmts[0].value {
+        Stmt::Let(comments, _, _) => {
+            assert_eq!(comments, &block.stmts[0].comments)
+        }
+        stmt => panic!("expected a let, found {:?}", stmt),
+    }
+
+    let top: Commented<Block> = Commented::parse_with(input, &keep).unwrap();
+    assert_eq!(top.doc(), Some("the block".to_string()));
+
+    // comments are only collected when asked for
+    let block = Block::parse(input).unwrap();
+    assert!(block.stmts.iter().all(|stmt| stmt.comments.is_empty()));
+}
+
+#[test]
+fn case_insensitive_heads() {
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Width {
+        bits: u64,
+    }
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    #[sexpy(nohead, case_insensitive)]
+    enum Decl {
+        #[sexpy(head = "port")]
+        Port(String, Width),
+        #[sexpy(head = "wire")]
+        Wire(String),
+    }
+
+    assert_eq!(
+        Decl::parse("(PORT foo (width 10))"),
+        Ok(Decl::Port("foo".to_string(), Width { bits: 10 }))
+    );
+    assert_eq!(Decl::parse("(Wire bar)"), Ok(Decl::Wire("bar".to_string())));
+    // only the heads of `Decl` ignore case
+    assert!(Decl::parse("(port foo (WIDTH 10))").is_err());
+    // errors use the spelling from the type definition
+    assert_eq!(
+        Decl::parse("(Wir bar)"),
+        Err("error: expected one of `port`, `wire`, found `Wir`
+ --> <input>:1:2
+  |
+1 | (Wir bar)
+  |  ^^^
+"
+        .to_string())
+    );
+}
+
+#[test]
+fn stream_parser() {
+    use sexpy::stream::{FormError, StreamError, StreamParser};
+    use std::io::BufReader;
+
+    #[derive(Sexpy, Debug, PartialEq)]
+    struct Event {
+        time: u64,
+        name: String,
+    }
+
+    let trace = "(event 0 start) (event 1
+  tick) #| a comment
+spanning lines |#
+(event two stop)
+(event 3 end)
+(event 4";
+    // a tiny buffer so that forms are split across refills
+    let reader = BufReader::with_capacity(4, trace.as_bytes());
+    let events: Vec<Result<Event, String>> = StreamParser::new(reader)
+        .map(|res| res.map_err(|e| e.to_string()))
+        .collect();
+    let event = |time, name: &str| {
+        Ok(Event {
+            time,
+            name: name.to_string(),
+        })
+    };
+    assert_eq!(
+        events,
+        vec![
+            event(0, "start"),
+            event(1, "tick"),
+            Err("error: in Digit, found 't'
+ --> <input>:4:8
+  |
+4 | (event two stop)
+  |        ^
+"
+            .to_string()),
+            event(3, "end"),
+            Err("error: unclosed '('
+ --> <input>:6:1
+  |
+6 | (event 4
+  | ^
+"
+            .to_string()),
+        ]
+    );
+
+    // mismatched delimiters hide where the form ends, so the stream stops
+    let events: Vec<Result<Event, String>> =
+        StreamParser::new("(event 1 a]\n(event 2 b)\n".as_bytes())
+            .map(|res| res.map_err(|e| e.to_string()))
+            .collect();
+    assert_eq!(events.len(), 1);
+    assert!(events[0].as_ref().unwrap_err().contains("1 | (event 1 a]"));
+
+    // forms on one line are dropped as they are parsed, and errors still
+    // point at their place in the stream
+    let line = "(event 0 caf\u{e9}) ".repeat(3) + "(event x y)";
+    let reader = BufReader::with_capacity(3, line.as_bytes());
+    let errors: Vec<FormError> = StreamParser::<_, Event>::new(reader)
+        .filter_map(|res| match res {
+            Err(StreamError::Parse(e)) => Some(e),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].diagnostic.span.start, line.find('x').unwrap());
+    assert!(errors[0].to_string().contains("<input>:1:53"));
+}